@@ -0,0 +1,16 @@
+// Set once per frame, laid out like FrameUniforms in uniforms.rs. MAX_LIGHTS is defined from Rust.
+
+struct Light {
+    vec4 direction; // the way the light travels, w unused
+    vec4 color;     // w unused
+};
+
+layout(std140, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    vec3 camera_position;
+    float time;
+    int light_count;
+    Light lights[MAX_LIGHTS];
+};
@@ -0,0 +1,12 @@
+#include "frame.glsl"
+
+// A surface of the given color lit by every light of the frame, from the side its normal faces
+vec3 lighting(vec3 color, vec3 normal)
+{
+    vec3 lit = vec3(0.0);
+    for (int i = 0; i < light_count; i++) {
+        vec3 lightDir = normalize(lights[i].direction.xyz);
+        lit += color * lights[i].color.rgb * max(dot(normal, -lightDir), 0);
+    }
+    return lit;
+}
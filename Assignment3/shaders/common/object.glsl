@@ -0,0 +1,7 @@
+// Set for every node drawn, laid out like ObjectUniforms in uniforms.rs
+
+layout(std140, binding = 1) uniform Object {
+    mat4 model;
+    mat4 model_view_projection;
+    float highlight; // how much to brighten the picked part, 0 for everything else
+};
@@ -4,10 +4,28 @@ layout(location=1) in vec4 in_color;
 layout(location=3) in vec3 in_normals;
 out vec4 out_color;
 
+// Variants are built with TEXTURED and UNLIT defined or not, see permutations.rs
+#ifdef TEXTURED
+layout(location=2) in vec2 in_texcoords;
+layout(binding = 1) uniform sampler2D albedo; // unit 0 holds the terrain heightmap
+#endif
+
+#include "common/lighting.glsl"
+#include "common/object.glsl"
+
 void main()
 {
-    vec3 lightDir = normalize(vec3(0.8, -0.5, 0.6));
-    vec3 color_lights = vec3(in_color[0], in_color[1], in_color[2]) * max(dot(in_normals, -lightDir), 0);
-    out_color = vec4(color_lights[0], color_lights[1], color_lights[2], in_color[3]);
+#ifdef TEXTURED
+    vec4 base_color = texture(albedo, in_texcoords);
+#else
+    vec4 base_color = in_color;
+#endif
+#ifdef UNLIT
+    vec3 color_lights = base_color.rgb;
+#else
+    vec3 color_lights = lighting(base_color.rgb, in_normals);
+#endif
+    color_lights = mix(color_lights, vec3(1.0, 0.85, 0.3), 0.5 * highlight);
+    out_color = vec4(color_lights[0], color_lights[1], color_lights[2], base_color[3]);
 
 }
\ No newline at end of file
@@ -9,21 +9,36 @@ layout(location = 0 ) in vec3 position;
 layout(location = 1) in vec4 in_color;
 layout(location = 1) out vec4 out_color;
 
-uniform layout(location = 2) mat4 transf_matrix; // used to transform the vertex positions
-
-// To store the normals
+// To store the normals, octahedral encoded in vertex formats with PACKED_NORMALS
+#ifdef PACKED_NORMALS
+layout(location = 4) in vec2 in_packed_normals;
+#else
 layout(location = 3) in vec3 in_normals;
+#endif
 layout(location = 3) out vec3 out_normals;
 
-uniform layout(location = 4) mat4 model_matrix; // used to transform the vertex normals
+#ifdef TEXTURED
+layout(location = 2) in vec2 in_texcoords;
+layout(location = 2) out vec2 out_texcoords;
+#endif
 
+#include "common/object.glsl"
+#include "common/octahedral.glsl"
 
 void main()
 {
     out_color = in_color;
-
-    out_normals = normalize(vec3(model_matrix * vec4(in_normals, 0.0))); // normalize the result
-
-    gl_Position = transf_matrix * vec4(position, 1.0f);
+#ifdef TEXTURED
+    out_texcoords = in_texcoords;
+#endif
+
+#ifdef PACKED_NORMALS
+    vec3 normals = octahedral_decode(in_packed_normals);
+#else
+    vec3 normals = in_normals;
+#endif
+    out_normals = normalize(vec3(model * vec4(normals, 0.0))); // normalize the result
+
+    gl_Position = model_view_projection * vec4(position, 1.0f);
 
 }
\ No newline at end of file
@@ -0,0 +1,29 @@
+#version 430 core
+
+// Pass-through stage for the tessellated terrain, all transformations happen after tessellation
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 in_color;
+#ifdef PACKED_NORMALS
+layout(location = 4) in vec2 in_packed_normals; // octahedral encoded
+#else
+layout(location = 3) in vec3 in_normals;
+#endif
+
+layout(location = 0) out vec3 tcs_position;
+layout(location = 1) out vec4 tcs_color;
+layout(location = 3) out vec3 tcs_normals;
+
+
+#include "common/octahedral.glsl"
+
+void main()
+{
+    tcs_position = position;
+    tcs_color = in_color;
+#ifdef PACKED_NORMALS
+    tcs_normals = octahedral_decode(in_packed_normals);
+#else
+    tcs_normals = in_normals;
+#endif
+}
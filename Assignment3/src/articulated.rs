@@ -0,0 +1,300 @@
+extern crate nalgebra_glm as glm;
+
+use gloom::mesh::{self, Mesh};
+use gloom::scene_graph::{Node, SceneNode};
+use std::fmt;
+
+// Articulated models: a set of parts taken from the objects of an OBJ file, attached to each other
+// in a hierarchy, each able to rotate about its own pivot. The description lives in a `.model`
+// file, see `models/helicopter.model` for the format.
+
+pub struct Part {
+    pub name   : String,
+    pub parent : Option<usize>, // Index of the part I am attached to, None for the root
+    pub pivot  : glm::Vec3,     // The point I rotate about, in model coordinates
+    pub axis   : glm::Vec3,     // The axis I rotate around when animated, along X, Y or Z
+    pub mesh   : Mesh,
+}
+
+pub struct ArticulatedModel {
+    pub parts: Vec<Part>, // Parents always come before their children
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    Obj(tobj::LoadError),
+    Syntax { line: usize, message: String },
+    MissingObject(String),
+    RootCount(usize),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(e)                   => write!(f, "{}", e),
+            ModelError::Obj(e)                  => write!(f, "failed to load model file: {}", e),
+            ModelError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ModelError::MissingObject(name)     => write!(f, "the model file has no object named {}", name),
+            ModelError::RootCount(count)        => write!(f, "expected exactly one part without a parent, found {}", count),
+        }
+    }
+}
+
+// A part as declared, before its mesh is loaded
+struct PartDescriptor {
+    name   : String,
+    object : String,
+    parent : Option<usize>,
+    color  : [f32; 4],
+    pivot  : glm::Vec3,
+    axis   : glm::Vec3,
+    subdivision : Option<(usize, f32)>, // levels and crease angle in radians
+}
+
+fn parse_floats<const N: usize>(arguments: &[&str], line: usize) -> Result<[f32; N], ModelError> {
+    let syntax = |message: String| ModelError::Syntax { line, message };
+    if arguments.len() != N {
+        return Err(syntax(format!("expected {} numbers, found {}", N, arguments.len())));
+    }
+    let mut values = [0.0; N];
+    for (value, argument) in values.iter_mut().zip(arguments) {
+        *value = argument.parse().map_err(|_| syntax(format!("{} is not a number", argument)))?;
+    }
+    Ok(values)
+}
+
+// Returns the OBJ path and the parts, in order of declaration
+fn parse(source: &str) -> Result<(String, Vec<PartDescriptor>), ModelError> {
+    let mut obj_path = None;
+    let mut parts: Vec<PartDescriptor> = vec![];
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let syntax = |message: String| ModelError::Syntax { line: line_number, message };
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        let (keyword, arguments) = match words.split_first() {
+            Some((keyword, arguments)) => (*keyword, arguments),
+            None => continue,
+        };
+
+        match keyword {
+            "obj" if arguments.len() == 1 => obj_path = Some(arguments[0].to_string()),
+            "part" if arguments.len() == 2 => {
+                if parts.iter().any(|p| p.name == arguments[0]) {
+                    return Err(syntax(format!("part {} is declared twice", arguments[0])));
+                }
+                parts.push(PartDescriptor {
+                    name   : arguments[0].to_string(),
+                    object : arguments[1].to_string(),
+                    parent : None,
+                    color  : [1.0, 1.0, 1.0, 1.0],
+                    pivot  : glm::zero(),
+                    axis   : glm::zero(),
+                    subdivision : None,
+                });
+            }
+            "parent" | "color" | "pivot" | "axis" | "subdivide" => {
+                let index = parts.len().checked_sub(1)
+                    .ok_or_else(|| syntax(format!("{} must follow a part", keyword)))?;
+                match keyword {
+                    "parent" if arguments.len() == 1 => {
+                        let parent = parts[..index].iter().position(|p| p.name == arguments[0])
+                            .ok_or_else(|| syntax(format!("parent {} is not declared before this part", arguments[0])))?;
+                        parts[index].parent = Some(parent);
+                    }
+                    "parent" => return Err(syntax("expected the name of a part".to_string())),
+                    "color" => parts[index].color = parse_floats::<4>(arguments, line_number)?,
+                    "pivot" => parts[index].pivot = parse_floats::<3>(arguments, line_number)?.into(),
+                    "subdivide" => {
+                        let [levels, angle] = parse_floats::<2>(arguments, line_number)?;
+                        if levels < 0.0 || levels.fract() != 0.0 {
+                            return Err(syntax(format!("{} is not a number of levels", levels)));
+                        }
+                        parts[index].subdivision = Some((levels as usize, angle.to_radians()));
+                    }
+                    _ => {
+                        // Node rotations are angles around X, Y and Z, which only turn about other axes
+                        // by chance
+                        let axis = parse_floats::<3>(arguments, line_number)?;
+                        if axis.iter().filter(|&&c| c != 0.0).count() != 1 {
+                            return Err(syntax(format!("axis {} {} {} is not along X, Y or Z", axis[0], axis[1], axis[2])));
+                        }
+                        parts[index].axis = axis.into();
+                    }
+                }
+            }
+            "obj" | "part" => return Err(syntax(format!("wrong number of arguments to {}", keyword))),
+            _ => return Err(syntax(format!("unknown keyword {}", keyword))),
+        }
+    }
+
+    let obj_path = obj_path.ok_or(ModelError::Syntax { line: 0, message: "no obj file given".to_string() })?;
+    let roots = parts.iter().filter(|p| p.parent.is_none()).count();
+    if roots != 1 {
+        return Err(ModelError::RootCount(roots));
+    }
+    Ok((obj_path, parts))
+}
+
+impl ArticulatedModel {
+    pub fn load(path: &str) -> Result<ArticulatedModel, ModelError> {
+        let source = std::fs::read_to_string(path).map_err(ModelError::Io)?;
+        let (obj_path, descriptors) = parse(&source)?;
+
+        println!("Loading articulated model {}...", path);
+        let before = std::time::Instant::now();
+        let models = mesh::load_obj(&obj_path).map_err(ModelError::Obj)?;
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms!", after.duration_since(before).as_micros() as f32 / 1e3);
+
+        let mut parts = Vec::with_capacity(descriptors.len());
+        for d in descriptors {
+            let model = models.iter().find(|m| m.name == d.object)
+                .ok_or_else(|| ModelError::MissingObject(d.object.clone()))?;
+            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+
+            let mut mesh = Mesh::from(model.mesh.clone(), d.color);
+            if let Some((levels, crease_angle)) = d.subdivision {
+                if !mesh.validate().is_valid() {
+                    mesh.repair();
+                }
+                mesh = mesh.loop_subdivide(&mesh.sharp_edges(crease_angle), levels);
+                println!("Subdivided {} to {} triangles.", d.name, mesh.indices.len() / 3);
+            }
+            parts.push(Part {
+                mesh,
+                name   : d.name,
+                parent : d.parent,
+                pivot  : d.pivot,
+                axis   : d.axis,
+            });
+        }
+        Ok(ArticulatedModel { parts })
+    }
+
+    // Builds the scene graph of one copy of the model. `create_node` makes the node drawing a part,
+    // typically with `SceneNode::from_mesh`. The hierarchy and pivots are set up here.
+    pub fn instantiate<F>(&self, mut create_node: F) -> ModelInstance
+        where F: FnMut(usize, &Part) -> Node
+    {
+        let mut nodes: Vec<Node> = Vec::with_capacity(self.parts.len());
+        for (i, part) in self.parts.iter().enumerate() {
+            let mut node = create_node(i, part);
+            node.reference_point = part.pivot;
+            if let Some(parent) = part.parent {
+                nodes[parent].add_child(&node);
+            }
+            nodes.push(node);
+        }
+        ModelInstance {
+            names : self.parts.iter().map(|p| p.name.clone()).collect(),
+            axes  : self.parts.iter().map(|p| p.axis).collect(),
+            root  : self.parts.iter().position(|p| p.parent.is_none()).unwrap(),
+            nodes,
+        }
+    }
+}
+
+// One copy of an articulated model in the scene graph
+pub struct ModelInstance {
+    names : Vec<String>,
+    axes  : Vec<glm::Vec3>,
+    root  : usize,
+    nodes : Vec<Node>,
+}
+
+impl ModelInstance {
+    // The node of the root part, which is what to attach to the rest of the scene and to move around
+    pub fn root(&mut self) -> &mut SceneNode {
+        &mut self.nodes[self.root]
+    }
+
+    pub fn part(&mut self, name: &str) -> Option<&mut SceneNode> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(&mut self.nodes[index])
+    }
+
+    // The name of the part drawn by `node`, if it is one of mine
+    pub fn part_name(&self, node: &SceneNode) -> Option<&str> {
+        let index = self.nodes.iter().position(|n| std::ptr::eq::<SceneNode>(&***n, node))?;
+        Some(&self.names[index])
+    }
+
+    // Rotates a part about its pivot by `angle` radians around its axis, which is along X, Y or Z like
+    // the angles of the rotation of a node
+    pub fn set_joint_angle(&mut self, name: &str, angle: f32) {
+        if let Some(index) = self.names.iter().position(|n| n == name) {
+            self.nodes[index].rotation = self.axes[index] * angle;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "obj ./resources/helicopter.obj  # the parts
+part body Body_body
+    color 0.3 0.3 0.3 1.0
+
+part main_rotor Main_Rotor_main_rotor
+    parent body
+    pivot 0.0 2.0 0.0
+    axis 0.0 1.0 0.0
+    subdivide 2 45
+
+part tail_rotor Tail_Rotor_tail_rotor
+    parent body
+    axis -1.0 0.0 0.0
+";
+
+    // The line of the syntax error in `source`, failing when there is none
+    fn error_line(source: &str) -> usize {
+        match parse(source) {
+            Err(ModelError::Syntax { line, .. }) => line,
+            Err(e) => panic!("expected a syntax error, got {}", e),
+            Ok(_) => panic!("expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn parses_a_valid_model() {
+        let (obj_path, parts) = parse(MODEL).unwrap();
+        assert_eq!(obj_path, "./resources/helicopter.obj");
+        let names: Vec<&str> = parts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["body", "main_rotor", "tail_rotor"]);
+        assert_eq!(parts[1].object, "Main_Rotor_main_rotor");
+        assert_eq!(parts.iter().map(|p| p.parent).collect::<Vec<_>>(), [None, Some(0), Some(0)]);
+        assert_eq!(parts[0].color, [0.3, 0.3, 0.3, 1.0]);
+        assert_eq!(parts[1].pivot, glm::vec3(0.0, 2.0, 0.0));
+        assert_eq!(parts[2].axis, glm::vec3(-1.0, 0.0, 0.0));
+        assert_eq!(parts[1].subdivision, Some((2, 45f32.to_radians())));
+        assert_eq!(parts[2].subdivision, None);
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_parts() {
+        assert_eq!(error_line("obj a.obj\npart body Body\npart rotor Rotor\n    parent tail\n"), 4);
+        assert_eq!(error_line("obj a.obj\npart body Body\npart body Door\n"), 3);
+        assert_eq!(error_line("obj a.obj\nparent body\n"), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_pivots_and_axes() {
+        assert_eq!(error_line("obj a.obj\npart body Body\n    pivot 1.0 2.0\n"), 3);
+        assert_eq!(error_line("obj a.obj\npart body Body\n    pivot 1.0 up 2.0\n"), 3);
+        assert_eq!(error_line("obj a.obj\npart body Body\n\n    axis 1.0 1.0 0.0\n"), 4);
+        assert_eq!(error_line("obj a.obj\npart body Body\n    axis 0 0 0\n"), 3);
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        // Parents must come first, so a part cannot be its own ancestor
+        assert_eq!(error_line("obj a.obj\npart body Body\n    parent body\n"), 3);
+        assert_eq!(error_line("obj a.obj\npart body Body\n    parent rotor\npart rotor Rotor\n    parent body\n"), 3);
+        // Nor can parts only have each other as parents, which leaves no root
+        assert!(matches!(parse("obj a.obj\n"), Err(ModelError::RootCount(0))));
+        assert!(matches!(parse("obj a.obj\npart body Body\npart door Door\n"), Err(ModelError::RootCount(2))));
+    }
+}
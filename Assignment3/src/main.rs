@@ -8,320 +8,325 @@
 #![allow(unused_variables)]
 */
 extern crate nalgebra_glm as glm;
-use glm::Mat4;
-use std::{ mem, ptr, os::raw::c_void };
+use std::ptr;
 use std::f32::consts::PI;
-use std::thread;
-use std::sync::{Mutex, Arc, RwLock};
+use std::rc::Rc;
 
-mod shader;
-mod mesh;
-mod util;
-mod scene_graph;
 mod toolbox;
-use scene_graph::SceneNode;
+mod articulated;
 
-use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
-use glutin::event_loop::ControlFlow;
+use gloom::{buffer, compute, debug_lines, mesh, permutations, scene_graph, shader, texture, uniforms, vertex_layout};
+use gloom::permutations::Features;
+use gloom::debug_output::DebugConfig;
+use gloom::scene_graph::SceneNode;
+use gloom::gpu_mesh::GpuMesh;
+use gloom::vertex_format::{VertexFormat, Precision};
+use gloom::uniforms::{FrameUniforms, ObjectUniforms, UniformBuffer};
+
+use glutin::event::{MouseButton, VirtualKeyCode};
 
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
-// == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
+// tessellated terrain, falls back to drawing the plain terrain triangles when disabled
+const TERRAIN_TESSELLATION: bool = true;
+const TERRAIN_HEIGHTMAP_PATH: &str = "./resources/lunarsurface_height.png"; // generated when missing, as below
+const TERRAIN_HEIGHTMAP_SIZE: u32 = 256;
+const TERRAIN_HEIGHTMAP_SEED: u32 = 1969;
+const TERRAIN_ALBEDO_PATH: &str = "./resources/lunarsurface_albedo.png"; // the terrain keeps its vertex colors without it
+const TERRAIN_DISPLACEMENT_SCALE: f32 = 2.0;
+const TERRAIN_TESSELLATION_RANGE: [f32; 3] = [10.0, 150.0, 16.0]; // full detail within 10 units, none beyond 150, at most 16 subdivisions
+
+// vertices closer than this in every attribute are merged after loading
+const WELD_EPSILON: f32 = 1e-6;
+
+// levels of detail of the helicopter parts, as (ratio of the triangles kept, screen size below which the level is used)
+const HELICOPTER_LODS: [(f32, f32); 3] = [(0.5, 0.3), (0.2, 0.1), (0.05, 0.03)];
+
+// how the vertices are stored on the GPU. The terrain is too large for half float positions to be precise.
+// Each format is drawn by the shader variant for its features, see VertexFormat::features.
+const TERRAIN_VERTEX_FORMAT: VertexFormat = VertexFormat { positions: Precision::Full, ..VertexFormat::COMPACT };
+const HELICOPTER_VERTEX_FORMAT: VertexFormat = VertexFormat::COMPACT;
+
+// the direction and color of the sunlight, the only light of the scene
+const SUN_DIRECTION: [f32; 3] = [0.8, -0.5, 0.6];
+const SUN_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+// where the scene is exported to when pressing P, with its colors in an MTL file next to it
+const SCENE_EXPORT_PATH: &str = "./scene.obj";
+
+// Where linked shader programs are cached, so they are only compiled again when their sources or the driver change
+const SHADER_CACHE_DIRECTORY: &str = "./shader_cache";
 
-// Get the size of an arbitrary array of numbers measured in bytes
-// Example usage:  pointer_to_array(my_array)
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+// Whether to ask the driver for debug messages, logged to stderr, and panic with a backtrace on OpenGL errors
+const GL_DEBUG_OUTPUT: bool = true;
+
+
+// Make a freshly loaded mesh safe to upload, merge the duplicated vertices of the OBJ and reorder for the vertex cache
+fn prepare_mesh(name: &str, mesh: &mut mesh::Mesh) {
+    let report = mesh.validate();
+    if !report.is_clean() {
+        println!("{}: {}", name, report);
+        println!("{}: repaired, {}", name, mesh.repair());
+    }
+    let welded = mesh.weld(WELD_EPSILON);
+    println!("{}: welded {} vertices, {}", name, welded, mesh.optimize());
 }
 
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-// Example usage:  pointer_to_array(my_array)
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
+// How much memory the vertices of a mesh take on the GPU, compared to the plain float layout
+fn print_vertex_footprint(name: &str, mesh: &mesh::Mesh, format: &VertexFormat) {
+    let vertex_count = mesh.vertices.len() / 3;
+    println!("{}: {} KiB of vertices at {} bytes each, down from {} KiB",
+             name,
+             vertex_count * format.vertex_size(mesh) / 1024,
+             format.vertex_size(mesh),
+             vertex_count * VertexFormat::FULL.vertex_size(mesh) / 1024);
 }
 
-// Get the size of the given type in bytes
-// Example usage:  size_of::<u64>()
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
+// The mesh moved along its normals by the heightmap, projected straight down onto its extent in the xz
+// plane, as terrain.tes does. Only the vertices move, so it is as close to the tessellated surface as the
+// mesh is fine.
+fn displace(mesh: &mesh::Mesh, heightmap: &texture::Heightmap, scale: f32) -> mesh::Mesh {
+    let (min, max) = mesh.bounding_box();
+    let mut displaced = mesh.clone();
+    for (position, normal) in displaced.vertices.chunks_exact_mut(3).zip(mesh.normals.chunks_exact(3)) {
+        let uv = glm::vec2((position[0] - min.x) / (max.x - min.x), (position[2] - min.z) / (max.z - min.z));
+        let height = heightmap.sample(uv) * scale;
+        for i in 0..3 {
+            position[i] += normal[i] * height;
+        }
+    }
+    displaced
 }
 
-// Get an offset in bytes for n units of type T, represented as a relative pointer
-// Example usage:  offset::<u64>(4)
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
+// Report where a shader would read other vertex attributes than those given by the layout
+fn check_vertex_layout(name: &str, layout: &vertex_layout::VertexLayout, shader: &shader::Shader) {
+    for mismatch in layout.validate(shader).iter().filter(|mismatch| mismatch.is_error()) {
+        println!("{}: vertex layout does not match the shader, {}", name, mismatch);
+    }
 }
 
-// Get a null pointer (equivalent to an offset of 0)
-// ptr::null()
+// Report where the uniform blocks of a shader are laid out differently from the structs filling them
+fn check_uniform_blocks(name: &str, shader: &shader::Shader) {
+    let mismatches = uniforms::validate::<FrameUniforms>(shader).into_iter().chain(uniforms::validate::<ObjectUniforms>(shader));
+    for mismatch in mismatches.filter(|mismatch| mismatch.is_error()) {
+        println!("{}: uniform block does not match the shader, {}", name, mismatch);
+    }
+}
 
+// Make sure compute shaders run and their writes can be read back, by having one fill a storage buffer.
+// Only run when asked for with --self-test, as in `cargo run -- --self-test`.
+fn check_compute() {
+    const COUNT: usize = 1000;
+    let squares = match unsafe { shader::Shader::from_files(&["./shaders/squares.comp"]) } {
+        Ok(shader) => shader,
+        Err(e) => return println!("Compute shader: {}", e),
+    };
+    let mut values = [0u32; COUNT];
+    unsafe {
+        let storage = buffer::Buffer::new(gl::SHADER_STORAGE_BUFFER, &[0; COUNT * 4], buffer::BufferUsage::Static);
+        storage.bind_base(0);
+        squares.dispatch_items([COUNT as u32, 1, 1]);
+        compute::memory_barrier(&[compute::Barrier::BufferUpdate]);
+        storage.read(0, std::slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, COUNT * 4));
+    }
+    if let Some(i) = (0..COUNT).find(|&i| values[i] != (i * i) as u32) {
+        println!("Compute shader: wrote {} at index {}, expected {}", values[i], i, i * i);
+    }
+}
 
-// == // Generate your VAO here
-// adjust to take another float vector as parameter -> normals
-// should contain color values -> rgba
-unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, rgba: &Vec<f32>, normals: &Vec<f32>) -> u32 {
-    // function must take vector of 3d vertex coord.
-    // vertex coord -> parameter 1, array of indices -> param 2
-    
-    // contents of buffer can be assumed to exclusively contain triangles
-
-    let mut vertexArrayID: u32 = 0; //type defined from paramters
-    let mut vertexBufferID: u32 = 0;
-    let mut vboColor: u32 = 0; //added for adding color
-    let mut vboNormal: u32 = 0; //added for the normals
-
-    //VAO setup    
-    //gets the ID of the generated VAO, must use ID to refer to the array
-    gl::GenVertexArrays(1, &mut vertexArrayID); //requires a pointer to a location where the IDs can be stored
-    //need to bind before linking VBO
-    gl::BindVertexArray(vertexArrayID);
-
-    //VBO setup
-    //create the VBO:
-    gl::GenBuffers(1, &mut vertexBufferID);
-    //need to bind before can be modified
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertexBufferID);
-    //filling the buffer
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&vertices), pointer_to_array(&vertices), gl::STATIC_DRAW);
-
-    let vap_index = 0; //specifies the index of the vertex pointer to set
-    //create a VAP (specifies where the vertex shader can obtain the data for a particular vertex attribute and how it is formatted)
-    gl::VertexAttribPointer(vap_index, 3, gl::FLOAT, gl::FALSE, 0, ptr::null()); //size 3 because we want xyz, single point so just using null ptr
-    //enabling the vertex attributes
-    gl::EnableVertexAttribArray(vap_index); 
-
-    //in order to combine vertices into primitives:
-    //generate and bind another buffer:
-    let mut indexBufferID: u32 = 0;
-    gl::GenBuffers(1, &mut indexBufferID);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, indexBufferID);
-    //fill it with data
-    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(&indices), pointer_to_array(&indices), gl::STATIC_DRAW);
-    //dont need to call VertexAttributPointer to set up IBO
-
-    //additional float vector should be put into a VBO, treat like earlier VBO setup
-    let color_index = 1;
-    gl::GenBuffers(1, &mut vboColor);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vboColor);
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&rgba), pointer_to_array(&rgba), gl::STATIC_DRAW);
-    gl::VertexAttribPointer(color_index, 4, gl::FLOAT, gl::FALSE, 0, ptr::null()); //bc colors consists of 4 floats
-    gl::EnableVertexAttribArray(color_index);
-
-    //generate a VBO to take in a vector of floats containing the normal vectors
-    gl::GenBuffers(1, &mut vboNormal);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vboNormal);
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&normals), pointer_to_array(&normals), gl::STATIC_DRAW);
-    gl::VertexAttribPointer(3, 3, gl::FLOAT, gl::FALSE, 0, ptr::null()); //3 because -> x, y and z coordinates
-    gl::EnableVertexAttribArray(3);
- 
-    //return VAO ID
-    vertexArrayID
+// Rebuild a shader whose files were edited, keeping the running program when the new one fails to build
+fn reload_shader(name: &str, shader: &mut shader::Shader) -> bool {
+    match unsafe { shader.reload_if_changed() } {
+        Ok(reloaded) => {
+            if reloaded {
+                println!("{}: reloaded", name);
+                check_uniform_blocks(name, shader);
+            }
+            reloaded
+        }
+        Err(e) => {
+            println!("{}: {}\n{}: keeping the previous program", name, e, name);
+            false
+        }
+    }
 }
 
+// Decimate a mesh into the helicopter levels of detail and upload each, as (GPU mesh, screen size)
+unsafe fn create_lod_meshes(mesh: &mesh::Mesh) -> Vec<(Rc<GpuMesh>, f32)> {
+    let ratios: Vec<f32> = HELICOPTER_LODS.iter().map(|lod| lod.0).collect();
+    let mut lods = vec![];
+    for (mut level, &(_, screen_size)) in mesh.lod_chain(&ratios).into_iter().zip(HELICOPTER_LODS.iter()) {
+        level.optimize();
+        lods.push((Rc::new(GpuMesh::new(&level, &HELICOPTER_VERTEX_FORMAT)), screen_size));
+    }
+    lods
+}
+
+// Fraction of the screen height covered by the bounding sphere of a node.
+// Row 1 of the view projection matrix is the y scale of the projection times the unit up axis of the
+// view, so its length recovers the projection scale without needing the matrices separately.
+fn screen_size(bounding_sphere: &glm::Vec4, view_projection_matrix: &glm::Mat4, model_matrix: &glm::Mat4) -> f32 {
+    let center = view_projection_matrix * model_matrix * glm::vec4(bounding_sphere.x, bounding_sphere.y, bounding_sphere.z, 1.0);
+    if center.w <= 0.0 {
+        return f32::INFINITY; // the camera is inside or in front of it
+    }
+    let projection_scale = glm::length(&glm::vec3(view_projection_matrix[(1, 0)], view_projection_matrix[(1, 1)], view_projection_matrix[(1, 2)]));
+    let model_scale = (0..3).map(|i| model_matrix.column(i).xyz().norm()).fold(0.0, f32::max);
+    bounding_sphere.w * model_scale * projection_scale / center.w
+}
+
+// The ray from the camera through a point on the screen, given in pixels from the top left corner.
+// The near and far points under the cursor are unprojected by the inverse view projection matrix.
+fn cursor_ray(cursor: (f32, f32), window_size: (f32, f32), view_projection_matrix: &glm::Mat4) -> mesh::Ray {
+    let x = 2.0 * cursor.0 / window_size.0 - 1.0;
+    let y = 1.0 - 2.0 * cursor.1 / window_size.1;
+    let inverse = glm::inverse(view_projection_matrix);
+    let unproject = |z: f32| {
+        let point = inverse * glm::vec4(x, y, z, 1.0);
+        point.xyz() / point.w
+    };
+    let near = unproject(-1.0);
+    mesh::Ray::new(near, unproject(1.0) - near)
+}
 
 // Create it to it to determine what to draw instead of just calling the draw function for each VAO manually
-unsafe fn draw_scene(node: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4, transformation_so_far: &glm::Mat4) {
+unsafe fn draw_scene(node: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4, transformation_so_far: &glm::Mat4,
+                     object_uniforms: &UniformBuffer<ObjectUniforms>, shaders: &mut permutations::ShaderPermutations) {
     
 // Perform any logic needed before drawing the node
-    let mut transformation_matrix: glm::Mat4 = glm::identity();
-    transformation_matrix = glm::translation(&-node.reference_point) * transformation_matrix; // translation to reference point
-    transformation_matrix = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0)) * transformation_matrix; // rotation on x
-    transformation_matrix = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0)) * transformation_matrix; // rotation on y
-    transformation_matrix = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0)) * transformation_matrix; // rotation on z
-    transformation_matrix = glm::translation(&node.position) * transformation_matrix; // translation
-    transformation_matrix = glm::translation(&node.reference_point) * transformation_matrix; // translation to origin
-
-    transformation_matrix = transformation_so_far * transformation_matrix; // multiplying with transformation so far
+    let transformation_matrix = transformation_so_far * node.local_transform(); // multiplying with transformation so far
 
 
     // Check if node is drawable, if so: set uniforms, bind VAO and draw VAO
-    if node.index_count > 0 {
-        let uniform_matrix = view_projection_matrix * transformation_matrix;
-        gl::BindVertexArray(node.vao_id);        
-        gl::UniformMatrix4fv(2, 1, gl::FALSE, (uniform_matrix).as_ptr()); // Model matrix to layout 1
-        gl::UniformMatrix4fv(4, 1, gl::FALSE, (transformation_matrix).as_ptr()); // Model View Projection matrix to layout 2
+    if let Some(gpu_mesh) = node.select_lod(screen_size(&node.bounding_sphere, view_projection_matrix, &transformation_matrix)) {
+        // Nodes with a program of their own are drawn with it, the others with the variant for their
+        // features, and not at all when that variant failed to build
+        let program_id = if node.program_id != 0 {
+            node.program_id
+        } else {
+            shaders.program(node.features).map_or(0, |shader| shader.program_id)
+        };
+        gl::UseProgram(program_id);
+
+        let highlight = if node.highlighted { 1.0 } else { 0.0 };
+        object_uniforms.set(&ObjectUniforms::new(transformation_matrix, view_projection_matrix, highlight));
+        gl::BindVertexArray(gpu_mesh.vao_id());
 
-        gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, ptr::null());
+        if node.draw_mode == gl::PATCHES {
+            gl::PatchParameteri(gl::PATCH_VERTICES, 3);
+        }
+        if program_id != 0 {
+            gl::DrawElements(node.draw_mode, gpu_mesh.index_count(), gl::UNSIGNED_INT, ptr::null());
+        }
     }
     // Recurse
     for &child in &node.children {
-        draw_scene(&*child, view_projection_matrix, &transformation_matrix);
+        draw_scene(&*child, view_projection_matrix, &transformation_matrix, object_uniforms, shaders);
     }
 }
 
 fn main() {
-    // Set up the necessary objects to deal with windows and event handling
-    let el = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new()
-        .with_title("Gloom-rs")
-        .with_resizable(true)
-        .with_inner_size(glutin::dpi::LogicalSize::new(INITIAL_SCREEN_W, INITIAL_SCREEN_H));
-    let cb = glutin::ContextBuilder::new()
-        .with_vsync(true);
-    let windowed_context = cb.build_windowed(wb, &el).unwrap();
-    // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
-    // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
-    // windowed_context.window().set_cursor_visible(false);
-
-    // Set up a shared vector for keeping track of currently pressed keys
-    let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
-    // Make a reference of this vector to send to the render thread
-    let pressed_keys = Arc::clone(&arc_pressed_keys);
-
-    // Set up shared tuple for tracking mouse movement between frames
-    let arc_mouse_delta = Arc::new(Mutex::new((0f32, 0f32)));
-    // Make a reference of this tuple to send to the render thread
-    let mouse_delta = Arc::clone(&arc_mouse_delta);
-
-    // Set up shared tuple for tracking changes to the window size
-    let arc_window_size = Arc::new(Mutex::new((INITIAL_SCREEN_W, INITIAL_SCREEN_H, false)));
-    // Make a reference of this tuple to send to the render thread
-    let window_size = Arc::clone(&arc_window_size);
-
-    // Spawn a separate thread for rendering, so event handling doesn't block rendering
-    let render_thread = thread::spawn(move || {
-        // Acquire the OpenGL Context and load the function pointers.
-        // This has to be done inside of the rendering thread, because
-        // an active OpenGL context cannot safely traverse a thread boundary
-        let context = unsafe {
-            let c = windowed_context.make_current().unwrap();
-            gl::load_with(|symbol| c.get_proc_address(symbol) as *const _);
-            c
-        };
+    let debug = DebugConfig { enabled: GL_DEBUG_OUTPUT, ..DebugConfig::default() };
+    gloom::window::run_with_debug("Gloom-rs", INITIAL_SCREEN_W, INITIAL_SCREEN_H, debug, |context, input| {
+        let gloom::window::Input { pressed_keys, mouse_delta, cursor_position, mouse_clicks, window_size } = input;
 
         let mut window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
-
-        // Set up openGL
-        unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
-            gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
-
-            // Print some diagnostics
-            println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
-            println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
-            println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
-        }
+        let initial_size = context.window().inner_size();
+        let mut window_pixels = (initial_size.width as f32, initial_size.height as f32);
 
         // == // Set up your VAO around here
         //create vao using vertices & indices
         // let my_vao = unsafe { create_vao(&vertices, &indices, &rgba) };
 
         //load terrain surface
-        let terrain_mesh = mesh::Terrain::load("./resources/lunarsurface.obj");
-
-	    //create vao for the terrain
-        let terrain_vao = unsafe { 
-            create_vao(
-                &terrain_mesh.vertices,
-                &terrain_mesh.indices,
-                &terrain_mesh.colors,
-                &terrain_mesh.normals
-            ) 
-        };
+        let mut terrain_mesh = mesh::Terrain::load("./resources/lunarsurface.obj");
 
-        //create the helicopter object
-        let helicopter_mesh = mesh::Helicopter::load("./resources/helicopter.obj");
-
-        //create a VAO for each part of the helicopter
-        //create vao for the body
-        let body_vao = unsafe { 
-            create_vao(
-                &helicopter_mesh.body.vertices,
-                &helicopter_mesh.body.indices,
-                &helicopter_mesh.body.colors,
-                &helicopter_mesh.body.normals
-            ) 
-        };
+        prepare_mesh("Terrain", &mut terrain_mesh);
 
-        //create vao for the door
-        let door_vao = unsafe { 
-            create_vao(
-                &helicopter_mesh.door.vertices,
-                &helicopter_mesh.door.indices,
-                &helicopter_mesh.door.colors,
-                &helicopter_mesh.door.normals
-            ) 
-        };
+	    //upload the terrain to the GPU
+        let terrain_gpu_mesh = Rc::new(unsafe { GpuMesh::new(&terrain_mesh, &TERRAIN_VERTEX_FORMAT) });
+        print_vertex_footprint("Terrain", &terrain_mesh, &TERRAIN_VERTEX_FORMAT);
 
-        //create vao for the main rotor
-        let main_rotor_vao = unsafe { 
-            create_vao(
-                &helicopter_mesh.main_rotor.vertices,
-                &helicopter_mesh.main_rotor.indices,
-                &helicopter_mesh.main_rotor.colors,
-                &helicopter_mesh.main_rotor.normals
-            ) 
-        };
+        //load the helicopter, whose parts and how they are attached are described in the model file
+        let mut helicopter = articulated::ArticulatedModel::load("./models/helicopter.model")
+            .unwrap_or_else(|e| panic!("Failed to load helicopter model: {}", e));
+        for part in helicopter.parts.iter_mut() {
+            prepare_mesh(&format!("Helicopter {}", part.name), &mut part.mesh);
+        }
 
-        //create vao for the tail rotor 
-        let tail_rotor_vao = unsafe { 
-            create_vao(
-                &helicopter_mesh.tail_rotor.vertices,
-                &helicopter_mesh.tail_rotor.indices,
-                &helicopter_mesh.tail_rotor.colors,
-                &helicopter_mesh.tail_rotor.normals
-            ) 
-        };
+        //upload each part of the helicopter, and simplified versions of each part for helicopters far away
+        let helicopter_gpu_meshes: Vec<Rc<GpuMesh>> = helicopter.parts.iter()
+            .map(|part| Rc::new(unsafe { GpuMesh::new(&part.mesh, &HELICOPTER_VERTEX_FORMAT) }))
+            .collect();
+        for part in &helicopter.parts {
+            print_vertex_footprint(&format!("Helicopter {}", part.name), &part.mesh, &HELICOPTER_VERTEX_FORMAT);
+        }
+        let helicopter_lods: Vec<Vec<(Rc<GpuMesh>, f32)>> = helicopter.parts.iter()
+            .map(|part| unsafe { create_lod_meshes(&part.mesh) })
+            .collect();
+        let helicopter_bvhs: Vec<Rc<mesh::Bvh>> = helicopter.parts.iter()
+            .map(|part| Rc::new(part.mesh.bvh()))
+            .collect();
+        let helicopter_meshes: Vec<Rc<mesh::Mesh>> = helicopter.parts.iter()
+            .map(|part| Rc::new(part.mesh.clone()))
+            .collect();
 
 	    //create the root of the scene
 	    let mut root_scene= SceneNode::new();
 
-        //create the terrain of the scene
-        let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain_mesh.index_count); 
+        // The terrain is displaced by a heightmap when tessellated, generated when there is none to load
+        let terrain_heightmap = texture::Heightmap::load(TERRAIN_HEIGHTMAP_PATH).unwrap_or_else(|e| {
+            println!("WARNING: could not load terrain heightmap {}: {}, generating one instead", TERRAIN_HEIGHTMAP_PATH, e);
+            texture::Heightmap::generate(TERRAIN_HEIGHTMAP_SIZE, TERRAIN_HEIGHTMAP_SEED)
+        });
 
-        //create a vector that has the helicopters in it
-        let mut helicopter_5nodes: Vec<scene_graph::Node> = Vec::new();
-        for n in 0..5 {
-            //Step 1: Generate one SceneNode for each object
-            let mut helicopter_body_node = SceneNode::from_vao(body_vao, helicopter_mesh.body.index_count);
-            let mut helicopter_door_node = SceneNode::from_vao(door_vao, helicopter_mesh.door.index_count);
-            let mut helicopter_main_rotor_node = SceneNode::from_vao(main_rotor_vao, helicopter_mesh.main_rotor.index_count);
-            let mut helicopter_tail_rotor_node = SceneNode::from_vao(tail_rotor_vao, helicopter_mesh.tail_rotor.index_count);
-
-            //Step 3: Initialise the values in the SceneNode data structure to their respective initial values, such as the position and starting rotations.
-            //helicopter_body_node.position = glm::vec3(10.0, 0.0, 0.0);;
-            helicopter_tail_rotor_node.reference_point = glm::Vec3::new(0.35, 2.3, 10.4); // Give to tail rotor node a reference point 
-	
-   	        //Step 2: Organise the objects into a Scene Graph by adding child nodes to their parentss list of children
-	        terrain_node.add_child(&helicopter_body_node);
-
-	        //the helicopter is relative to the terrain and it is in obvious
-            //that the door, main rotor and tail rotor are relative to the body
-	        helicopter_body_node.add_child(&helicopter_door_node);
-            helicopter_body_node.add_child(&helicopter_main_rotor_node);
-            helicopter_body_node.add_child(&helicopter_tail_rotor_node);
-
-            //Finally pushing the helicopter to the vector
-            helicopter_5nodes.push(helicopter_body_node)
+        //create the terrain of the scene, picked and exported as it is drawn
+        let displaced_terrain = match TERRAIN_TESSELLATION {
+            true => displace(&terrain_mesh, &terrain_heightmap, TERRAIN_DISPLACEMENT_SCALE),
+            false => terrain_mesh.clone(),
+        };
+        let mut terrain_node = SceneNode::from_mesh(terrain_gpu_mesh);
+        terrain_node.bvh = Some(Rc::new(displaced_terrain.bvh()));
+        terrain_node.mesh = Some(Rc::new(displaced_terrain));
+        terrain_node.name = "terrain".to_string();
+        terrain_node.features = TERRAIN_VERTEX_FORMAT.features();
+
+        // The albedo stays bound to unit 1, where the TEXTURED variants read it. The tessellated terrain
+        // projects it straight down like the heightmap, the plain one needs texture coordinates of its own.
+        if TERRAIN_TESSELLATION || !terrain_mesh.texcoords.is_empty() {
+            match unsafe { texture::load_albedo(TERRAIN_ALBEDO_PATH) } {
+                Ok(albedo) => unsafe {
+                    gl::ActiveTexture(gl::TEXTURE1);
+                    gl::BindTexture(gl::TEXTURE_2D, albedo);
+                    terrain_node.features = terrain_node.features | Features::TEXTURED;
+                },
+                Err(e) => println!("WARNING: could not load terrain albedo {}: {}, the terrain keeps its vertex colors", TERRAIN_ALBEDO_PATH, e),
+            }
         }
 
-	    //Step 1: Generate one SceneNode for each object
-        //let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain_mesh.index_count); 
-        //let mut helicopter_body_node = SceneNode::from_vao(body_vao, helicopter_mesh.body.index_count);
-        //let mut helicopter_door_node = SceneNode::from_vao(door_vao, helicopter_mesh.door.index_count);
-        //let mut helicopter_main_rotor_node = SceneNode::from_vao(main_rotor_vao, helicopter_mesh.main_rotor.index_count);
-        //let mut helicopter_tail_rotor_node = SceneNode::from_vao(tail_rotor_vao, helicopter_mesh.tail_rotor.index_count);
-	
-        //Step 3: Initialise the values in the SceneNode data structure to their respective initial values, such as the position and starting rotations.
-        //helicopter_body_node.position = glm::vec3(10.0, 0.0, 0.0);;
-        //helicopter_tail_rotor_node.reference_point = glm::Vec3::new(0.35, 2.3, 10.4); // Give to tail rotor node a reference point 
-	
-	    //Step 2: Organise the objects into a Scene Graph by adding child nodes to their parentss list of children
-	    //terrain_node.add_child(&helicopter_body_node);
-
-	    //the helicopter is relative to the terrain and it is in obvious
-        //that the door, main rotor and tail rotor are relative to the body
-	    //helicopter_body_node.add_child(&helicopter_door_node);
-        //helicopter_body_node.add_child(&helicopter_main_rotor_node);
-        //helicopter_body_node.add_child(&helicopter_tail_rotor_node);
+        //create a vector that has the helicopters in it, each one attached to the terrain
+        let mut helicopters: Vec<articulated::ModelInstance> = Vec::new();
+        for n in 0..5 {
+            let mut instance = helicopter.instantiate(|i, part| {
+                let mut node = SceneNode::from_mesh(Rc::clone(&helicopter_gpu_meshes[i]));
+                node.features = HELICOPTER_VERTEX_FORMAT.features();
+                node.bvh = Some(Rc::clone(&helicopter_bvhs[i]));
+                node.mesh = Some(Rc::clone(&helicopter_meshes[i]));
+                node.name = format!("helicopter{}_{}", n, part.name);
+
+                //attach the levels of detail, which are chosen by how large each part is on screen
+                let (center, radius) = part.mesh.bounding_sphere();
+                node.bounding_sphere = glm::vec4(center.x, center.y, center.z, radius);
+                for (gpu_mesh, screen_size) in &helicopter_lods[i] {
+                    node.add_lod(Rc::clone(gpu_mesh), *screen_size);
+                }
+                node
+            });
+            terrain_node.add_child(instance.root());
+            helicopters.push(instance);
+        }
 
         //Step4: Connect the terrain to a single root node for the entire scene
         root_scene.add_child(&terrain_node);
@@ -337,21 +342,80 @@ fn main() {
         // of just using the correct path), but it only needs to be called once
 
         //part 2 ->loading & linking shader (3.3 in openGL book)
-        let simple_shader = unsafe {
-            shader::ShaderBuilder::new()
-                .attach_file("./shaders/simple.frag")
-                .attach_file("./shaders/simple.vert")
-                .link()
+        // The shaders size their arrays of lights to match FrameUniforms
+        let max_lights = uniforms::MAX_LIGHTS.to_string();
+        let shader_defines = [("MAX_LIGHTS", max_lights.as_str())];
+        // The scene is drawn with a variant of the simple shader for the features of each node, built
+        // when first drawn. The default one is built right away, so a broken shader stops the program early.
+        let mut simple_shaders = permutations::ShaderPermutations::new(&["./shaders/simple.frag", "./shaders/simple.vert"], &shader_defines)
+            .binary_cache(SHADER_CACHE_DIRECTORY);
+        if unsafe { simple_shaders.program(Features::NONE) }.is_none() {
+            panic!("The simple shader failed to build");
+        }
+
+        // The terrain is tessellated on the GPU and displaced by a heightmap, shaded like everything else
+        let terrain_defines: Vec<(&str, &str)> = shader_defines.iter().cloned()
+            .chain(terrain_node.features.defines().map(|name| (name, "1")))
+            .collect();
+        let mut terrain_shader = unsafe {
+            shader::Shader::from_files_cached(&["./shaders/terrain.vert", "./shaders/terrain.tcs", "./shaders/terrain.tes", "./shaders/simple.frag"],
+                                              &terrain_defines, SHADER_CACHE_DIRECTORY)
+                .unwrap_or_else(|e| panic!("{}", e))
         };
+        let heightmap = unsafe { terrain_heightmap.upload() };
+        if TERRAIN_TESSELLATION {
+            terrain_node.draw_mode = gl::PATCHES;
+            terrain_node.program_id = terrain_shader.program_id;
+        }
 
-        //enabling the program object
-        unsafe {
-            gl::UseProgram(simple_shader.program_id);
+        // The heightmap covers the terrain's extent in the xz plane. Set again whenever the program is reloaded.
+        let (terrain_min, terrain_max) = terrain_mesh.bounding_box();
+        let set_terrain_uniforms = |terrain_shader: &shader::Shader| unsafe {
+            terrain_shader.set_vec3("tessellation_range", &glm::make_vec3(&TERRAIN_TESSELLATION_RANGE));
+            terrain_shader.set_vec4("heightmap_bounds", &glm::vec4(terrain_min.x, terrain_min.z, terrain_max.x - terrain_min.x, terrain_max.z - terrain_min.z));
+            terrain_shader.set_f32("displacement_scale", TERRAIN_DISPLACEMENT_SCALE);
+            terrain_shader.set_texture("heightmap", 0, heightmap);
+        };
+        set_terrain_uniforms(&terrain_shader);
+
+        // The uniform blocks are bound once, and read by both programs
+        let frame_uniforms = unsafe { UniformBuffer::<FrameUniforms>::new() };
+        let object_uniforms = unsafe { UniformBuffer::<ObjectUniforms>::new() };
+        let simple_shader = unsafe { simple_shaders.program(Features::NONE) }.unwrap();
+        check_uniform_blocks("Simple shader", simple_shader);
+        check_uniform_blocks("Terrain shader", &terrain_shader);
+        if std::env::args().any(|arg| arg == "--self-test") {
+            check_compute();
+        }
+        let sun = uniforms::Light {
+            direction : glm::vec4(SUN_DIRECTION[0], SUN_DIRECTION[1], SUN_DIRECTION[2], 0.0),
+            color     : glm::vec4(SUN_COLOR[0], SUN_COLOR[1], SUN_COLOR[2], 0.0),
         };
 
+        // Make sure the shaders read the vertex attributes where the VAOs put them
+        if TERRAIN_TESSELLATION {
+            check_vertex_layout("Terrain", &TERRAIN_VERTEX_FORMAT.layout(&terrain_mesh), &terrain_shader);
+        } else if let Some(terrain_program) = unsafe { simple_shaders.program(terrain_node.features) } {
+            check_vertex_layout("Terrain", &TERRAIN_VERTEX_FORMAT.layout(&terrain_mesh), terrain_program);
+        }
+        if let Some(helicopter_program) = unsafe { simple_shaders.program(HELICOPTER_VERTEX_FORMAT.features()) } {
+            for part in &helicopter.parts {
+                check_vertex_layout(&format!("Helicopter {}", part.name), &HELICOPTER_VERTEX_FORMAT.layout(&part.mesh), helicopter_program);
+            }
+        }
+
         // Used to demonstrate keyboard handling for exercise 2.
         let mut _arbitrary_number = 0.0; // feel free to remove
 
+        // The helicopter and part under the cursor, which is highlighted
+        let mut picked: Option<(usize, String)> = None;
+
+        // Lines to show for a single frame, like the normal of the surface under the cursor
+        let mut debug_lines = unsafe { debug_lines::DebugLines::new(256) };
+
+        // Whether the export key was down the previous frame, to export once per press
+        let mut export_key_was_down = false;
+
         let mut pos = glm::vec3(0.0, 0.0, 0.0);
         let mut rot = glm::vec2(0.0, 0.0);
 
@@ -366,26 +430,31 @@ fn main() {
             let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
             previous_frame_time = now;
 
-            //(Step 3) Set values for helicopter body and rotors movement/rotations
-            //let heading = toolbox::simple_heading_animation(elapsed);
-            //helicopter_body_node.position.x = heading.x;
-            //helicopter_body_node.position.z = heading.z;
-            //helicopter_body_node.rotation.x = heading.pitch;
-            //helicopter_body_node.rotation.y = heading.yaw;
-            //helicopter_body_node.rotation.z = heading.roll;
-            //helicopter_tail_rotor_node.rotation.x = 2.0 * elapsed;
-            //helicopter_main_rotor_node.rotation.y = 2.0 * elapsed;
-
             //(Step 3) Set values for 5 helicopter bodies and rotors movement/rotations
-            for n in 0..5 {
+            for (n, helicopter) in helicopters.iter_mut().enumerate() {
                 let heading = toolbox::simple_heading_animation(elapsed + (n as f32 * 0.95));
-                helicopter_5nodes[n].position.x = heading.x;
-                helicopter_5nodes[n].position.z = heading.z;
-                helicopter_5nodes[n].rotation.x = heading.pitch;
-                helicopter_5nodes[n].rotation.y = heading.yaw;
-                helicopter_5nodes[n].rotation.z = heading.roll;
-                helicopter_5nodes[n].get_child(1).rotation.y = 2.0 * elapsed; //main rotor
-                helicopter_5nodes[n].get_child(2).rotation.x = 2.0 * elapsed; //tail rotor
+                let body = helicopter.root();
+                body.position.x = heading.x;
+                body.position.z = heading.z;
+                body.rotation.x = heading.pitch;
+                body.rotation.y = heading.yaw;
+                body.rotation.z = heading.roll;
+                helicopter.set_joint_angle("main_rotor", 2.0 * elapsed);
+                helicopter.set_joint_angle("tail_rotor", 2.0 * elapsed);
+            }
+
+            // Pick up edits to the shader files, the programs only change when the new ones build
+            for (features, shader) in simple_shaders.variants_mut() {
+                reload_shader(&format!("Simple shader ({})", features), shader);
+            }
+            for features in unsafe { simple_shaders.retry_failed() } {
+                println!("Simple shader ({}): built after failing before", features);
+            }
+            if reload_shader("Terrain shader", &mut terrain_shader) {
+                set_terrain_uniforms(&terrain_shader);
+                if TERRAIN_TESSELLATION {
+                    terrain_node.program_id = terrain_shader.program_id;
+                }
             }
 
             // Handle resize events
@@ -393,7 +462,8 @@ fn main() {
                 if new_size.2 {
                     context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
                     window_aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-                    (*new_size).2 = false;
+                    window_pixels = (new_size.0 as f32, new_size.1 as f32);
+                    new_size.2 = false;
                     println!("Window was resized to {}x{}", new_size.0, new_size.1);
                     unsafe { gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32); }
                 }
@@ -403,6 +473,17 @@ fn main() {
             // need 2 keys for each rotations/translation axis. one for forward direction of motion and one for backward
             // default -> WASD, Space, and Lshift
             if let Ok(keys) = pressed_keys.lock() {
+                // Export the scene as it is drawn, once per press of P
+                let export_key_down = keys.contains(&VirtualKeyCode::P);
+                if export_key_down && !export_key_was_down {
+                    let mut export = mesh::ObjExport::default();
+                    match root_scene.export(&mut export, &glm::identity()).and_then(|_| export.save(SCENE_EXPORT_PATH)) {
+                        Ok(()) => println!("Exported the scene to {}", SCENE_EXPORT_PATH),
+                        Err(e) => println!("Could not export the scene to {}: {}", SCENE_EXPORT_PATH, e),
+                    }
+                }
+                export_key_was_down = export_key_down;
+
                 for key in keys.iter() {
                     match key {
                         // The `VirtualKeyCode` enum is defined here:
@@ -465,23 +546,53 @@ fn main() {
             }
 
             // == // Please compute camera transforms here (exercise 2 & 3)
-            let mut transf_matrix: glm::Mat4 = glm::identity();
            // let mut theta = 0.0;
 
-           transf_matrix *= glm::perspective(1.0, PI/2.0, 1.0, 1000.0); //flips the z-axis
+           let projection_matrix: glm::Mat4 = glm::perspective(window_aspect_ratio, PI/2.0, 1.0, 1000.0); //flips the z-axis
            //to ensure drawing isnt out of view:
-           transf_matrix *= glm::translation(&glm::vec3(0.0, 0.0, -1.5)) * glm::translation(&pos);
+           let mut view_matrix: glm::Mat4 = glm::translation(&glm::vec3(0.0, 0.0, -1.5)) * glm::translation(&pos);
            //mimic behavior of camera- wasd, lrup
-           transf_matrix *= glm::rotation(rot[0], &glm::vec3(1.0, 0.0, 0.0)) * glm::rotation(rot[1], &glm::vec3(0.0, 1.0, 0.0));
+           view_matrix *= glm::rotation(rot[0], &glm::vec3(1.0, 0.0, 0.0)) * glm::rotation(rot[1], &glm::vec3(0.0, 1.0, 0.0));
+           let transf_matrix = projection_matrix * view_matrix;
+
+            // Pick what is under the cursor: highlight the helicopter part there, and report it when clicked
+            let cursor = cursor_position.lock().map_or((0.0, 0.0), |cursor| *cursor);
+            let mut clicked = false;
+            if let Ok(mut clicks) = mouse_clicks.lock() {
+                clicked = clicks.contains(&MouseButton::Left);
+                clicks.clear(); // reset when done
+            }
+            let ray = cursor_ray(cursor, window_pixels, &transf_matrix);
+            let mut now_picked = None;
+            if let Some(hit) = root_scene.raycast(&ray, &glm::identity()) {
+                debug_lines.line(&hit.point, &(hit.point + hit.normal * 2.0), [1.0, 0.85, 0.3, 1.0]);
+                now_picked = helicopters.iter().enumerate()
+                    .find_map(|(n, helicopter)| helicopter.part_name(hit.node).map(|part| (n, part.to_string())));
+                if clicked {
+                    let what = match &now_picked {
+                        Some((n, part)) => format!("helicopter {}, {}", n, part),
+                        None => "the terrain".to_string(),
+                    };
+                    println!("Clicked {} at [{:.2}, {:.2}, {:.2}], triangle {}", what, hit.point.x, hit.point.y, hit.point.z, hit.triangle);
+                }
+            }
+            if now_picked != picked {
+                for (n, part) in picked.iter().chain(now_picked.iter()) {
+                    let node = helicopters[*n].part(part).unwrap();
+                    node.highlighted = !node.highlighted;
+                }
+                picked = now_picked;
+            }
+
+            // The camera sits at the origin of view space, the terrain tessellation is based on the distance to it
+            let camera_position = glm::vec4_to_vec3(&(glm::inverse(&view_matrix) * glm::vec4(0.0, 0.0, 0.0, 1.0)));
 
             unsafe {
                 // Clear the color and depth buffers
                 gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky, full opacity
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-                //creating transf matrix using uniform matrix property gl, p.33
-                //using location 2 from simple.vert
-                gl::UniformMatrix4fv(2, 1, gl::FALSE, transf_matrix.as_ptr()); //need to use as ptr as recommended in assignment
+                frame_uniforms.set(&FrameUniforms::new(view_matrix, projection_matrix, camera_position, elapsed, &[sun]));
 
                 // == // Issue the necessary gl:: commands to draw your scene here
                 //first step of drawing a VAO is to bind it
@@ -490,86 +601,21 @@ fn main() {
 		        //helicopter_body_node.position = glm::vec3(10.0, 0.0, 0.0);
 
                 
-		        let mut transformation: glm::Mat4 = glm::identity();
-                draw_scene(&root_scene, &transf_matrix, &transformation);
+		        let transformation: glm::Mat4 = glm::identity();
+                draw_scene(&root_scene, &transf_matrix, &transformation, &object_uniforms, &mut simple_shaders);
+
+                // The debug lines are drawn unlit, with the variant looked up every frame as it may have been reloaded
+                if let Some(simple_shader) = simple_shaders.program(Features::UNLIT) {
+                    simple_shader.activate();
+                    debug_lines.draw(&transf_matrix, &object_uniforms);
+                }
             }
 
             // Display the new color buffer on the display
             context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
-        }
-    });
 
-    // == //
-    // == // From here on down there are only internals.
-    // == //
-
-    // Keep track of the health of the rendering thread
-    let render_thread_healthy = Arc::new(RwLock::new(true));
-    let render_thread_watchdog = Arc::clone(&render_thread_healthy);
-    thread::spawn(move || {
-        if !render_thread.join().is_ok() {
-            if let Ok(mut health) = render_thread_watchdog.write() {
-                println!("Render thread panicked!");
-                *health = false;
-            }
-        }
-    });
-
-    // Start the event loop -- This is where window events are initially handled
-    el.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
-
-        // Terminate program if render thread panics
-        if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
-                *control_flow = ControlFlow::Exit;
-            }
-        }
-
-        match event {
-            Event::WindowEvent { event: WindowEvent::Resized(physical_size), .. } => {
-                println!("New window size received: {}x{}", physical_size.width, physical_size.height);
-                if let Ok(mut new_size) = arc_window_size.lock() {
-                    *new_size = (physical_size.width, physical_size.height, true);
-                }
-            }
-            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
-                *control_flow = ControlFlow::Exit;
-            }
-            // Keep track of currently pressed keys to send to the rendering thread
-            Event::WindowEvent { event: WindowEvent::KeyboardInput {
-                    input: KeyboardInput { state: key_state, virtual_keycode: Some(keycode), .. }, .. }, .. } => {
-
-                if let Ok(mut keys) = arc_pressed_keys.lock() {
-                    match key_state {
-                        Released => {
-                            if keys.contains(&keycode) {
-                                let i = keys.iter().position(|&k| k == keycode).unwrap();
-                                keys.remove(i);
-                            }
-                        },
-                        Pressed => {
-                            if !keys.contains(&keycode) {
-                                keys.push(keycode);
-                            }
-                        }
-                    }
-                }
-
-                // Handle Escape and Q keys separately
-                match keycode {
-                    Escape => { *control_flow = ControlFlow::Exit; }
-                    Q      => { *control_flow = ControlFlow::Exit; }
-                    _      => { }
-                }
-            }
-            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
-                // Accumulate mouse movement
-                if let Ok(mut position) = arc_mouse_delta.lock() {
-                    *position = (position.0 + delta.0 as f32, position.1 + delta.1 as f32);
-                }
-            }
-            _ => { }
+            // Panic with any OpenGL error reported during the frame
+            gloom::debug_output::check();
         }
     });
 }
@@ -0,0 +1,22 @@
+use gloom::permutations::Features;
+use std::path::Path;
+
+// Every shader of the assignment, in every variant the scene can ask for, without a GPU
+#[test]
+fn shaders_are_valid() {
+    let features = [Features::TEXTURED, Features::UNLIT, Features::PACKED_NORMALS];
+    let variants = (0..1 << features.len()).map(|bits| {
+        (0..features.len()).filter(|i| bits & (1 << i) != 0).fold(Features::NONE, |variant, i| variant | features[i])
+    });
+    for features in variants {
+        let mut defines = vec![("MAX_LIGHTS".to_string(), gloom::uniforms::MAX_LIGHTS.to_string())];
+        defines.extend(features.defines().map(|name| (name.to_string(), "1".to_string())));
+
+        let report = shader_check::check_directory(Path::new("shaders"), &defines).unwrap();
+        let problems: Vec<String> = report.problems.iter().map(|problem| problem.to_string()).collect();
+        assert!(problems.is_empty(), "{} variant:\n{}", features, problems.join("\n"));
+        let skipped: Vec<String> = report.skipped.iter().map(|skipped| skipped.to_string()).collect();
+        assert!(skipped.is_empty(), "{} variant skipped:\n{}", features, skipped.join("\n"));
+        assert_eq!(report.checked, 6);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::debug_output::{self, MessageType, Severity};
+use std::cell::Cell;
+use std::{ptr, os::raw::c_void};
+
+// OpenGL buffers, deleted when dropped.
+//
+// `Buffer` is a plain buffer with a usage hint, which can be updated in part or replaced as a whole.
+// Its data goes through the copy binding points, so writing an index buffer does not attach it to
+// whichever VAO is bound. Binding it to its target, as a VAO needs its index buffer, is up to `bind`.
+// `RingBuffer` is for data written anew every frame: it is split into segments, one for each frame
+// in flight, and each frame writes to the next segment while the GPU may still read the others. A
+// fence after each frame's draw calls tells when a segment is free to be written again. It is mapped
+// persistently where OpenGL 4.4 is available, and otherwise copies each segment in when done. Like
+// `Buffer`, it only binds itself to the copy binding points.
+// Every frame goes: begin, write the segment, end, draw from `offset`, advance.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferUsage {
+    Static,  // Written once, drawn many times
+    Dynamic, // Written now and then, drawn many times in between
+    Stream,  // Written about as often as it is drawn
+}
+
+impl From<BufferUsage> for gl::types::GLenum {
+    fn from(usage: BufferUsage) -> Self {
+        match usage {
+            BufferUsage::Static  => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream  => gl::STREAM_DRAW,
+        }
+    }
+}
+
+pub struct Buffer {
+    id     : u32,
+    target : u32,         // gl::ARRAY_BUFFER, gl::ELEMENT_ARRAY_BUFFER, ...
+    usage  : BufferUsage,
+    size   : Cell<usize>, // Bytes
+}
+
+impl Buffer {
+    pub unsafe fn new(target: u32, data: &[u8], usage: BufferUsage) -> Buffer {
+        let mut id: u32 = 0;
+        gl::GenBuffers(1, &mut id);
+        let buffer = Buffer { id, target, usage, size: Cell::new(0) };
+        buffer.set_data(data);
+        buffer
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn size(&self) -> usize {
+        self.size.get()
+    }
+
+    // Binds the buffer to its target. For gl::ELEMENT_ARRAY_BUFFER that makes it the index buffer of
+    // the bound VAO.
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    // Replaces all the contents. The old storage is orphaned rather than overwritten, so draw
+    // calls still reading it need not finish first.
+    pub unsafe fn set_data(&self, data: &[u8]) {
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+        gl::BufferData(gl::COPY_WRITE_BUFFER, data.len() as isize, data.as_ptr() as *const c_void, self.usage.into());
+        self.size.set(data.len());
+    }
+
+    // Overwrites `data.len()` bytes from `offset`, which must lie within the buffer
+    pub unsafe fn update(&self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= self.size.get(), "update of {} bytes at {} is out of the buffer of {} bytes",
+                data.len(), offset, self.size.get());
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+        gl::BufferSubData(gl::COPY_WRITE_BUFFER, offset as isize, data.len() as isize, data.as_ptr() as *const c_void);
+    }
+
+    // Binds the whole buffer to binding point `binding` of its target, which must be an indexed one
+    // such as gl::SHADER_STORAGE_BUFFER or gl::UNIFORM_BUFFER, for the blocks declared with that binding
+    pub unsafe fn bind_base(&self, binding: u32) {
+        gl::BindBufferBase(self.target, binding, self.id);
+    }
+
+    // Copies `data.len()` bytes from `offset` back from the GPU, waiting for the commands writing them.
+    // Writes of shaders must be made visible first, with a `compute::Barrier::BufferUpdate` barrier.
+    pub unsafe fn read(&self, offset: usize, data: &mut [u8]) {
+        assert!(offset + data.len() <= self.size.get(), "read of {} bytes at {} is out of the buffer of {} bytes",
+                data.len(), offset, self.size.get());
+        gl::BindBuffer(gl::COPY_READ_BUFFER, self.id);
+        gl::GetBufferSubData(gl::COPY_READ_BUFFER, offset as isize, data.len() as isize, data.as_mut_ptr() as *mut c_void);
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) };
+    }
+}
+
+pub struct RingBuffer {
+    id           : u32,
+    segment_size : usize,
+    fences       : Vec<gl::types::GLsync>, // One for each segment, null when the GPU is done with it
+    current      : usize,                  // The segment written this frame
+    mapping      : *mut u8,                // The whole buffer, persistently mapped, or null
+    staging      : Vec<u8>,                // The current segment, when the buffer is not mapped
+}
+
+// How long to wait for the GPU to release a segment, in nanoseconds, and how many times before giving
+// up on it, as when the context is lost or the GPU hangs
+const FENCE_TIMEOUT: u64 = 1_000_000_000;
+const FENCE_WAITS: u32 = 5;
+
+// Ids of the messages reported through the debug output
+const MAPPING_FAILED: u32 = 1;
+const FENCE_TIMED_OUT: u32 = 2;
+const FENCE_FAILED: u32 = 3;
+
+impl RingBuffer {
+    // A buffer of `segments` segments of `segment_size` bytes each. Three let the CPU write one
+    // segment while the GPU reads another, with one to spare for the frame queued in between.
+    pub unsafe fn new(segment_size: usize, segments: usize) -> RingBuffer {
+        let mut id: u32 = 0;
+        gl::GenBuffers(1, &mut id);
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, id);
+        let total_size = (segment_size * segments) as isize;
+
+        let mut mapping = ptr::null_mut();
+        if gl::BufferStorage::is_loaded() && gl::MapBufferRange::is_loaded() {
+            let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+            gl::BufferStorage(gl::COPY_WRITE_BUFFER, total_size, ptr::null(), flags);
+            mapping = gl::MapBufferRange(gl::COPY_WRITE_BUFFER, 0, total_size, flags) as *mut u8;
+            if mapping.is_null() {
+                // The storage is immutable once given, so the copies go to a buffer of their own
+                debug_output::report(MAPPING_FAILED, MessageType::Performance, Severity::Medium,
+                                     "Could not map a ring buffer persistently, copying each segment into it instead".to_string());
+                gl::DeleteBuffers(1, &id);
+                gl::GenBuffers(1, &mut id);
+                gl::BindBuffer(gl::COPY_WRITE_BUFFER, id);
+            }
+        }
+        if mapping.is_null() {
+            gl::BufferData(gl::COPY_WRITE_BUFFER, total_size, ptr::null(), gl::STREAM_DRAW);
+        }
+
+        RingBuffer {
+            id,
+            segment_size,
+            fences  : vec![ptr::null(); segments],
+            current : 0,
+            mapping,
+            staging : if mapping.is_null() { vec![0; segment_size] } else { vec![] },
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Bytes from the start of the buffer to the current segment, to point draw calls at it
+    pub fn offset(&self) -> usize {
+        self.current * self.segment_size
+    }
+
+    // The current segment, to be written before `end`. Blocks while the GPU still reads from it.
+    pub unsafe fn begin(&mut self) -> &mut [u8] {
+        let fence = std::mem::replace(&mut self.fences[self.current], ptr::null());
+        if !fence.is_null() {
+            for wait in 1..=FENCE_WAITS {
+                match gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT) {
+                    gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => break,
+                    gl::TIMEOUT_EXPIRED if wait < FENCE_WAITS => { },
+                    gl::TIMEOUT_EXPIRED => debug_output::report(FENCE_TIMED_OUT, MessageType::Performance, Severity::High,
+                        format!("The GPU has not released a ring buffer segment in {} seconds, writing it anyway",
+                                FENCE_WAITS as u64 * FENCE_TIMEOUT / 1_000_000_000)),
+                    _ => {
+                        debug_output::report(FENCE_FAILED, MessageType::Error, Severity::High,
+                                             "Waiting for a ring buffer segment failed, writing it anyway".to_string());
+                        break;
+                    }
+                }
+            }
+            gl::DeleteSync(fence);
+        }
+
+        if self.mapping.is_null() {
+            &mut self.staging
+        } else {
+            std::slice::from_raw_parts_mut(self.mapping.add(self.offset()), self.segment_size)
+        }
+    }
+
+    // Marks the first `written` bytes of the current segment as written, before drawing from it
+    pub unsafe fn end(&mut self, written: usize) {
+        if self.mapping.is_null() && written > 0 {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+            gl::BufferSubData(gl::COPY_WRITE_BUFFER, self.offset() as isize, written.min(self.segment_size) as isize,
+                              self.staging.as_ptr() as *const c_void);
+        }
+    }
+
+    // Fences the draw calls reading the current segment and moves on to the next one
+    pub unsafe fn advance(&mut self) {
+        self.fences[self.current] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        self.current = (self.current + 1) % self.fences.len();
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            for &fence in &self.fences {
+                if !fence.is_null() {
+                    gl::DeleteSync(fence);
+                }
+            }
+            if !self.mapping.is_null() {
+                gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+                gl::UnmapBuffer(gl::COPY_WRITE_BUFFER);
+            }
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Compute shaders run outside of the draw pipeline, on buffers and images rather than on vertices and
+// fragments, as for particles simulated on the GPU or terrain generated there (OpenGL 4.3).
+//
+// A compute program is built from a single .comp file like any other program, and run with
+// `Shader::dispatch`. It reads and writes shader storage blocks, through buffers bound with
+// `Buffer::bind_base` to the `binding` the block declares, and images, through textures bound with
+// `bind_image`. Its writes are not ordered with the commands that follow: before drawing from what it
+// wrote, or reading it back, a `memory_barrier` for the way it will be read is needed.
+
+// The ways the results of a dispatch can be read afterwards, each needing its own barrier
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Barrier {
+    VertexAttribArray, // Drawn from as vertex attributes
+    ElementArray,      // Drawn from as indices
+    Uniform,           // Read as a uniform block
+    TextureFetch,      // Sampled as a texture
+    ShaderImageAccess, // Loaded from as an image by another dispatch or shader
+    Command,           // Read as the arguments of an indirect draw or dispatch
+    TextureUpdate,     // Read back or overwritten with glGetTexImage, glTexSubImage and the like
+    BufferUpdate,      // Read back or overwritten with `Buffer::read`, `Buffer::update` and the like
+    ShaderStorage,     // Read as a shader storage block by another dispatch or shader
+    All,
+}
+
+impl From<Barrier> for gl::types::GLbitfield {
+    fn from(barrier: Barrier) -> Self {
+        match barrier {
+            Barrier::VertexAttribArray => gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT,
+            Barrier::ElementArray      => gl::ELEMENT_ARRAY_BARRIER_BIT,
+            Barrier::Uniform           => gl::UNIFORM_BARRIER_BIT,
+            Barrier::TextureFetch      => gl::TEXTURE_FETCH_BARRIER_BIT,
+            Barrier::ShaderImageAccess => gl::SHADER_IMAGE_ACCESS_BARRIER_BIT,
+            Barrier::Command           => gl::COMMAND_BARRIER_BIT,
+            Barrier::TextureUpdate     => gl::TEXTURE_UPDATE_BARRIER_BIT,
+            Barrier::BufferUpdate      => gl::BUFFER_UPDATE_BARRIER_BIT,
+            Barrier::ShaderStorage     => gl::SHADER_STORAGE_BARRIER_BIT,
+            Barrier::All               => gl::ALL_BARRIER_BITS,
+        }
+    }
+}
+
+// Makes the memory writes of the shaders so far visible to the commands after it that read in any of
+// the given ways
+pub unsafe fn memory_barrier(barriers: &[Barrier]) {
+    let bits = barriers.iter().fold(0, |bits, &barrier| bits | gl::types::GLbitfield::from(barrier));
+    if bits != 0 {
+        gl::MemoryBarrier(bits);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageAccess {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+// Binds level 0 of a 2D texture to image unit `unit`, for the image uniform declared with that
+// binding. `format` is the internal format the shader declares, such as gl::R16 or gl::RGBA32F.
+pub unsafe fn bind_image(unit: u32, texture_id: u32, access: ImageAccess, format: u32) {
+    let access = match access {
+        ImageAccess::ReadOnly  => gl::READ_ONLY,
+        ImageAccess::WriteOnly => gl::WRITE_ONLY,
+        ImageAccess::ReadWrite => gl::READ_WRITE,
+    };
+    gl::BindImageTexture(unit, texture_id, 0, gl::FALSE, 0, access, format);
+}
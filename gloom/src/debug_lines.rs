@@ -0,0 +1,80 @@
+extern crate nalgebra_glm as glm;
+
+use crate::buffer::RingBuffer;
+use crate::uniforms::{ObjectUniforms, UniformBuffer};
+use crate::vertex_format::{COLOR_LOCATION, POSITION_LOCATION};
+use crate::vertex_layout::{VertexAttribute, VertexLayout};
+use std::{mem, slice};
+
+// Lines drawn for one frame only, such as normals or rays, streamed through a ring buffer.
+// They have no normals, and are drawn with the UNLIT variant of the simple shader to show in full color.
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineVertex {
+    position : [f32; 3],
+    color    : [u8; 4],
+}
+
+pub struct DebugLines {
+    ring         : RingBuffer,
+    vao_id       : u32,
+    vertices     : Vec<LineVertex>, // Lines added this frame, two vertices each
+    max_vertices : usize,           // How many fit in one segment of the ring buffer
+}
+
+impl DebugLines {
+    // Room for `max_lines` lines each frame, any more are dropped
+    pub unsafe fn new(max_lines: usize) -> DebugLines {
+        let max_vertices = max_lines * 2;
+        let ring = RingBuffer::new(max_vertices * mem::size_of::<LineVertex>(), 3);
+
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        gl::BindVertexArray(vao_id);
+        VertexLayout::new()
+            .buffer(&[
+                VertexAttribute::new("position", POSITION_LOCATION, 3, gl::FLOAT, false),
+                VertexAttribute::new("in_color", COLOR_LOCATION, 4, gl::UNSIGNED_BYTE, true),
+            ])
+            .apply(&[ring.id()]);
+        gl::BindVertexArray(0);
+
+        DebugLines { ring, vao_id, vertices: Vec::with_capacity(max_vertices), max_vertices }
+    }
+
+    pub fn line(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        if self.vertices.len() + 2 > self.max_vertices {
+            return;
+        }
+        let color = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        for p in [from, to].iter() {
+            self.vertices.push(LineVertex { position: [p.x, p.y, p.z], color });
+        }
+    }
+
+    // Draws the lines added since the last call, in world coordinates, and forgets them.
+    // The UNLIT variant of the simple shader must be active.
+    pub unsafe fn draw(&mut self, view_projection_matrix: &glm::Mat4, object_uniforms: &UniformBuffer<ObjectUniforms>) {
+        let segment = self.ring.begin();
+        let bytes = slice::from_raw_parts(self.vertices.as_ptr() as *const u8, mem::size_of_val(&self.vertices[..]));
+        segment[..bytes.len()].copy_from_slice(bytes);
+        self.ring.end(bytes.len());
+
+        if !self.vertices.is_empty() {
+            object_uniforms.set(&ObjectUniforms::new(glm::identity(), view_projection_matrix, 0.0));
+            gl::BindVertexArray(self.vao_id);
+            let first = self.ring.offset() / mem::size_of::<LineVertex>();
+            gl::DrawArrays(gl::LINES, first as i32, self.vertices.len() as i32);
+            gl::BindVertexArray(0);
+        }
+        self.ring.advance();
+        self.vertices.clear();
+    }
+}
+
+impl Drop for DebugLines {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao_id) };
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr};
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+
+// OpenGL debug output (OpenGL 4.3 or KHR_debug), the messages drivers give about errors, undefined
+// behavior and performance along with the calls causing them.
+//
+// Each message is copied out of the driver's buffer as it arrives, and then goes through the filters
+// of a `DebugConfig`: ids known to be noise are dropped, as are messages below the least severity
+// wanted, and an id repeated more than a number of times is muted. What passes goes to the logger.
+// Messages of type error can be made to panic, with a backtrace of the call that caused them.
+//
+// Messages are delivered synchronously, on the thread making the call, so the backtrace points at it.
+// A panic cannot unwind out of the driver calling back, so the first error is only recorded there, with
+// its backtrace, and `check` panics with it once the call has returned, as after each frame.
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Severity {
+    Notification,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Api,
+    WindowSystem,
+    ShaderCompiler,
+    ThirdParty,
+    Application,
+    Other,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+    Error,
+    DeprecatedBehavior,
+    UndefinedBehavior,
+    Portability,
+    Performance,
+    Marker,
+    PushGroup,
+    PopGroup,
+    Other,
+}
+
+pub struct DebugMessage {
+    pub source       : Source,
+    pub message_type : MessageType,
+    pub id           : u32,         // Chosen by the driver, the same for every message of one kind
+    pub severity     : Severity,
+    pub text         : String,
+}
+
+pub struct DebugConfig {
+    pub enabled        : bool,                 // Whether to ask for a debug context and its messages at all
+    pub min_severity   : Severity,             // Less severe messages are dropped
+    pub ignored_ids    : Vec<u32>,             // Known noise, such as notes on where buffers are placed
+    pub repeat_limit   : u32,                  // How often one id is logged before it is muted, 0 for no limit
+    pub panic_on_error : bool,                 // Whether messages of type error make `check` panic, with a backtrace
+    pub logger         : fn(&DebugMessage),    // Where the messages that pass the filters go
+}
+
+impl Default for DebugConfig {
+    // Enabled in debug builds, logging all but notifications to stderr and panicking on errors
+    fn default() -> DebugConfig {
+        DebugConfig {
+            enabled        : cfg!(debug_assertions),
+            min_severity   : Severity::Low,
+            ignored_ids    : vec![],
+            repeat_limit   : 10,
+            panic_on_error : true,
+            logger         : log_to_stderr,
+        }
+    }
+}
+
+impl Severity {
+    fn from_gl(severity: u32) -> Severity {
+        match severity {
+            gl::DEBUG_SEVERITY_HIGH   => Severity::High,
+            gl::DEBUG_SEVERITY_MEDIUM => Severity::Medium,
+            gl::DEBUG_SEVERITY_LOW    => Severity::Low,
+            _                         => Severity::Notification,
+        }
+    }
+}
+
+impl Source {
+    fn from_gl(source: u32) -> Source {
+        match source {
+            gl::DEBUG_SOURCE_API             => Source::Api,
+            gl::DEBUG_SOURCE_WINDOW_SYSTEM   => Source::WindowSystem,
+            gl::DEBUG_SOURCE_SHADER_COMPILER => Source::ShaderCompiler,
+            gl::DEBUG_SOURCE_THIRD_PARTY     => Source::ThirdParty,
+            gl::DEBUG_SOURCE_APPLICATION     => Source::Application,
+            _                                => Source::Other,
+        }
+    }
+}
+
+impl MessageType {
+    fn from_gl(message_type: u32) -> MessageType {
+        match message_type {
+            gl::DEBUG_TYPE_ERROR               => MessageType::Error,
+            gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => MessageType::DeprecatedBehavior,
+            gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR  => MessageType::UndefinedBehavior,
+            gl::DEBUG_TYPE_PORTABILITY         => MessageType::Portability,
+            gl::DEBUG_TYPE_PERFORMANCE         => MessageType::Performance,
+            gl::DEBUG_TYPE_MARKER              => MessageType::Marker,
+            gl::DEBUG_TYPE_PUSH_GROUP          => MessageType::PushGroup,
+            gl::DEBUG_TYPE_POP_GROUP           => MessageType::PopGroup,
+            _                                  => MessageType::Other,
+        }
+    }
+}
+
+impl fmt::Display for DebugMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {:?} from {:?}, id {}: {}", self.severity, self.message_type, self.source, self.id, self.text)
+    }
+}
+
+// The default logger, prefixing each message with a level told by its type and severity
+pub fn log_to_stderr(message: &DebugMessage) {
+    let level = match (message.message_type, message.severity) {
+        (MessageType::Error, _) | (_, Severity::High) => "error",
+        (MessageType::UndefinedBehavior, _) | (_, Severity::Medium) => "warning",
+        (_, Severity::Low) => "info",
+        (_, Severity::Notification) => "debug",
+    };
+    eprintln!("GL {}: {}", level, message);
+}
+
+struct DebugOutput {
+    config  : DebugConfig,
+    repeats : Mutex<HashMap<u32, u32>>, // How often each id was logged
+    error   : Mutex<Option<String>>,    // The first error not yet raised by `check`, with its backtrace
+}
+
+impl DebugOutput {
+    fn new(config: DebugConfig) -> DebugOutput {
+        DebugOutput { config, repeats: Mutex::new(HashMap::new()), error: Mutex::new(None) }
+    }
+
+    fn handle(&self, message: DebugMessage) {
+        let config = &self.config;
+        // Errors are kept whatever the filters, which only decide what is logged
+        if config.panic_on_error && message.message_type == MessageType::Error {
+            if let Ok(mut error) = self.error.lock() {
+                if error.is_none() {
+                    *error = Some(format!("{}\n{}", message, std::backtrace::Backtrace::force_capture()));
+                }
+            }
+        }
+        if message.severity < config.min_severity || config.ignored_ids.contains(&message.id) {
+            return;
+        }
+
+        let count = match self.repeats.lock() {
+            Ok(mut repeats) => {
+                let count = repeats.entry(message.id).or_insert(0);
+                *count += 1;
+                *count
+            }
+            Err(_) => 1,
+        };
+        if config.repeat_limit == 0 || count <= config.repeat_limit {
+            (config.logger)(&message);
+        }
+        if count == config.repeat_limit {
+            eprintln!("GL: muting id {} after {} messages", message.id, count);
+        }
+    }
+
+    // The error recorded since the last call, if any
+    fn take_error(&self) -> Option<String> {
+        self.error.lock().ok().and_then(|mut error| error.take())
+    }
+}
+
+// The output installed for the current context, null when there is none
+static INSTALLED: AtomicPtr<DebugOutput> = AtomicPtr::new(ptr::null_mut());
+
+extern "system" fn debug_callback(
+    source: u32, message_type: u32, id: u32,
+    severity: u32, _length: i32,
+    text: *const gl::types::GLchar, user_param: *mut c_void
+) {
+    // The text is the driver's, valid only during the call, and null terminated. `_length` is not
+    // relied upon, as some drivers count the terminator and some do not.
+    let text = if text.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(text) }.to_string_lossy().into_owned()
+    };
+    let output = unsafe { &*(user_param as *const DebugOutput) };
+    output.handle(DebugMessage {
+        source       : Source::from_gl(source),
+        message_type : MessageType::from_gl(message_type),
+        id,
+        severity     : Severity::from_gl(severity),
+        text,
+    });
+}
+
+// Starts handing the messages of the current context to `config`, which lives as long as the program
+// does. Does nothing when disabled, or when the context offers no debug output.
+pub unsafe fn install(config: DebugConfig) {
+    if !config.enabled || !gl::DebugMessageCallback::is_loaded() {
+        gl::Disable(gl::DEBUG_OUTPUT);
+        return;
+    }
+    let output: &'static mut DebugOutput = Box::leak(Box::new(DebugOutput::new(config)));
+    INSTALLED.store(output, Ordering::Release);
+    gl::Enable(gl::DEBUG_OUTPUT);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(debug_callback), output as *const DebugOutput as *const c_void);
+}
+
+// Reports a problem the program found itself, through the same filters and logger as the messages of
+// the driver, or to stderr when no debug output is installed. `id` tells one kind of message from another.
+pub fn report(id: u32, message_type: MessageType, severity: Severity, text: String) {
+    let message = DebugMessage { source: Source::Application, message_type, id, severity, text };
+    let output = INSTALLED.load(Ordering::Acquire);
+    if output.is_null() {
+        log_to_stderr(&message);
+    } else {
+        unsafe { &*output }.handle(message);
+    }
+}
+
+// Panics with the first OpenGL error of type error since the last check, and the backtrace of the call
+// that caused it, when `panic_on_error` is set. Meant to be called on the render thread, as after
+// swapping the buffers of each frame.
+pub fn check() {
+    let output = INSTALLED.load(Ordering::Acquire);
+    if output.is_null() {
+        return;
+    }
+    if let Some(error) = unsafe { &*output }.take_error() {
+        panic!("OpenGL error: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        // The ids logged on this thread, as each test runs on a thread of its own
+        static LOGGED: RefCell<Vec<u32>> = const { RefCell::new(vec![]) };
+    }
+
+    fn log_id(message: &DebugMessage) {
+        LOGGED.with(|logged| logged.borrow_mut().push(message.id));
+    }
+
+    fn logged() -> Vec<u32> {
+        LOGGED.with(|logged| logged.borrow_mut().drain(..).collect())
+    }
+
+    fn output_with(config: DebugConfig) -> DebugOutput {
+        DebugOutput::new(DebugConfig { logger: log_id, ..config })
+    }
+
+    fn message(id: u32, message_type: MessageType, severity: Severity) -> DebugMessage {
+        DebugMessage { source: Source::Api, message_type, id, severity, text: format!("message {}", id) }
+    }
+
+    #[test]
+    fn filters_by_severity_and_id() {
+        let output = output_with(DebugConfig { min_severity: Severity::Medium, ignored_ids: vec![7], ..DebugConfig::default() });
+        output.handle(message(1, MessageType::Performance, Severity::Low));
+        output.handle(message(7, MessageType::Other, Severity::High));
+        output.handle(message(2, MessageType::Performance, Severity::Medium));
+        output.handle(message(3, MessageType::UndefinedBehavior, Severity::High));
+        assert_eq!(logged(), [2, 3]);
+    }
+
+    #[test]
+    fn mutes_repeated_ids() {
+        let output = output_with(DebugConfig { repeat_limit: 3, ..DebugConfig::default() });
+        for _ in 0..5 {
+            output.handle(message(5, MessageType::Performance, Severity::Medium));
+        }
+        output.handle(message(6, MessageType::Performance, Severity::Medium));
+        assert_eq!(logged(), [5, 5, 5, 6]);
+
+        let unlimited = output_with(DebugConfig { repeat_limit: 0, ..DebugConfig::default() });
+        for _ in 0..20 {
+            unlimited.handle(message(5, MessageType::Performance, Severity::Medium));
+        }
+        assert_eq!(logged().len(), 20);
+    }
+
+    #[test]
+    fn records_the_first_error_whatever_the_filters() {
+        let output = output_with(DebugConfig { ignored_ids: vec![1], repeat_limit: 1, ..DebugConfig::default() });
+        output.handle(message(1, MessageType::Error, Severity::High));
+        output.handle(message(2, MessageType::Error, Severity::High));
+        let error = output.take_error().unwrap();
+        assert!(error.starts_with("High Error from Api, id 1: message 1"), "{}", error);
+        assert!(output.take_error().is_none());
+
+        let quiet = output_with(DebugConfig { panic_on_error: false, ..DebugConfig::default() });
+        quiet.handle(message(3, MessageType::Error, Severity::High));
+        assert!(quiet.take_error().is_none());
+        assert_eq!(logged(), [2, 3]);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::buffer::{Buffer, BufferUsage};
+use crate::mesh::Mesh;
+use crate::vertex_format::VertexFormat;
+use crate::vertex_layout::VertexLayout;
+use std::cell::{Cell, Ref, RefCell};
+use std::slice;
+
+// A mesh uploaded to the GPU: the VAO, the buffers it reads from and how many indices to draw.
+//
+// Everything is deleted when the GpuMesh is dropped, which has to happen on the thread that owns
+// the OpenGL context, as with every other gl:: call. A GpuMesh given to a scene node is never
+// dropped, see the limitation on `scene_graph::Node`. The data can be uploaded again at any time,
+// also while shared, as the VAO keeps its name and only its buffers change. Meshes changing often
+// should be created with a dynamic or streaming usage, and can have parts of their vertices updated.
+pub struct GpuMesh {
+    vao_id         : u32,
+    usage          : BufferUsage,
+    index_buffer   : Buffer,
+    vertex_buffers : RefCell<Vec<Buffer>>,
+    layout         : RefCell<VertexLayout>,
+    index_count    : Cell<i32>,
+}
+
+fn as_bytes(indices: &[u32]) -> &[u8] {
+    unsafe { slice::from_raw_parts(indices.as_ptr() as *const u8, std::mem::size_of_val(indices)) }
+}
+
+impl GpuMesh {
+    pub unsafe fn new(mesh: &Mesh, format: &VertexFormat) -> GpuMesh {
+        GpuMesh::with_usage(mesh, format, BufferUsage::Static)
+    }
+
+    pub unsafe fn with_usage(mesh: &Mesh, format: &VertexFormat, usage: BufferUsage) -> GpuMesh {
+        let gpu_mesh = GpuMesh::empty(usage);
+        gpu_mesh.upload(mesh, format);
+        gpu_mesh
+    }
+
+    // From vertex data already laid out, with one stream of bytes for each buffer of the layout
+    pub unsafe fn from_streams(layout: &VertexLayout, streams: &[&[u8]], indices: &[u32], usage: BufferUsage) -> GpuMesh {
+        let gpu_mesh = GpuMesh::empty(usage);
+        gpu_mesh.upload_streams(layout, streams, indices);
+        gpu_mesh
+    }
+
+    unsafe fn empty(usage: BufferUsage) -> GpuMesh {
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        let index_buffer = Buffer::new(gl::ELEMENT_ARRAY_BUFFER, &[], usage);
+        // Binding the index buffer attaches it to whichever VAO is bound, so that must be this one
+        gl::BindVertexArray(vao_id);
+        index_buffer.bind();
+        gl::BindVertexArray(0);
+        GpuMesh {
+            vao_id,
+            usage,
+            index_buffer,
+            vertex_buffers : RefCell::new(vec![]),
+            layout         : RefCell::new(VertexLayout::new()),
+            index_count    : Cell::new(0),
+        }
+    }
+
+    // Replaces the vertices and indices with those of `mesh`
+    pub unsafe fn upload(&self, mesh: &Mesh, format: &VertexFormat) {
+        let streams = format.pack(mesh);
+        let streams: Vec<&[u8]> = streams.iter().map(|stream| stream.as_slice()).collect();
+        self.upload_streams(&format.layout(mesh), &streams, &mesh.indices);
+    }
+
+    // Replaces the vertices and indices, the layout may differ from the previous one
+    pub unsafe fn upload_streams(&self, layout: &VertexLayout, streams: &[&[u8]], indices: &[u32]) {
+        assert_eq!(streams.len(), layout.buffers.len(), "one stream is needed for each buffer of the layout");
+        gl::BindVertexArray(self.vao_id);
+
+        let mut vertex_buffers = self.vertex_buffers.borrow_mut();
+        // Whether a mesh has texture coordinates can change the number of buffers
+        vertex_buffers.truncate(streams.len());
+        for (i, stream) in streams.iter().enumerate() {
+            match vertex_buffers.get(i) {
+                Some(buffer) => buffer.set_data(stream),
+                None => vertex_buffers.push(Buffer::new(gl::ARRAY_BUFFER, stream, self.usage)),
+            }
+        }
+        let mut current_layout = self.layout.borrow_mut();
+        current_layout.unapply();
+        layout.apply(&vertex_buffers.iter().map(Buffer::id).collect::<Vec<u32>>());
+        *current_layout = layout.clone();
+
+        self.index_buffer.set_data(as_bytes(indices));
+        self.index_count.set(indices.len() as i32);
+
+        gl::BindVertexArray(0);
+    }
+
+    // Overwrites part of the vertex buffer `buffer`, from `offset` bytes in. The layout stays the same.
+    pub unsafe fn update_vertices(&self, buffer: usize, offset: usize, data: &[u8]) {
+        self.vertex_buffers.borrow()[buffer].update(offset, data);
+    }
+
+    // Overwrites the vertices from `first_vertex` on with those of `mesh`, which has the same
+    // attributes as the mesh uploaded last, in the same format
+    pub unsafe fn update_mesh_vertices(&self, mesh: &Mesh, format: &VertexFormat, first_vertex: usize) {
+        let layout = self.layout.borrow();
+        for (i, (stream, buffer)) in format.pack(mesh).iter().zip(layout.buffers.iter()).enumerate() {
+            self.update_vertices(i, first_vertex * buffer.stride, stream);
+        }
+    }
+
+    // Overwrites part of the indices, from index `first` on. The number of indices stays the same.
+    pub unsafe fn update_indices(&self, first: usize, indices: &[u32]) {
+        self.index_buffer.update(first * std::mem::size_of::<u32>(), as_bytes(indices));
+    }
+
+    pub fn vao_id(&self) -> u32 {
+        self.vao_id
+    }
+
+    pub fn index_count(&self) -> i32 {
+        self.index_count.get()
+    }
+
+    // How the vertices are laid out in the buffers
+    pub fn layout(&self) -> Ref<'_, VertexLayout> {
+        self.layout.borrow()
+    }
+}
+
+// The buffers delete themselves
+impl Drop for GpuMesh {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao_id) };
+    }
+}
@@ -0,0 +1,23 @@
+// The code shared by the assignments: windowing, shaders, meshes and the scene graph, and the
+// buffers and vertex layouts to put them on the GPU. Each assignment is a binary on top of this.
+// Every unsafe function here is unsafe for the same reason: it calls OpenGL, and needs the context
+// current on the calling thread.
+#![allow(clippy::missing_safety_doc)]
+
+extern crate nalgebra_glm as glm;
+
+pub mod buffer;
+pub mod compute;
+pub mod debug_lines;
+pub mod debug_output;
+pub mod gpu_mesh;
+pub mod mesh;
+pub mod permutations;
+pub mod scene_graph;
+pub mod shader;
+pub mod texture;
+pub mod uniforms;
+pub mod util;
+pub mod vertex_format;
+pub mod vertex_layout;
+pub mod window;
@@ -0,0 +1,98 @@
+extern crate nalgebra_glm as glm;
+use tobj;
+
+// internal helper
+fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
+    color.iter().cloned().cycle().take(num*4).collect()
+}
+
+mod simplify;
+mod optimize;
+mod validate;
+mod cache;
+mod subdivide;
+mod raycast;
+mod export;
+
+pub use cache::load_obj;
+pub use raycast::{Bvh, Ray};
+pub use export::ObjExport;
+pub use subdivide::PolygonMesh;
+
+// Mesh
+
+#[derive(Clone)]
+pub struct Mesh {
+    pub vertices    : Vec<f32>,
+    pub normals     : Vec<f32>,
+    pub colors      : Vec<f32>,
+    pub texcoords   : Vec<f32>, // empty if the model has no texture coordinates
+    pub indices     : Vec<u32>,
+    pub index_count : i32,
+}
+
+impl Mesh {
+    pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
+        let num_verts = mesh.positions.len() / 3;
+        let index_count = mesh.indices.len() as i32;
+        Mesh {
+            vertices: mesh.positions,
+            normals: mesh.normals,
+            texcoords: mesh.texcoords,
+            indices: mesh.indices,
+            colors: generate_color_vec(color, num_verts),
+            index_count,
+        }
+    }
+
+    // Axis aligned bounds of the vertex positions, as (minimum corner, maximum corner)
+    pub fn bounding_box(&self) -> (glm::Vec3, glm::Vec3) {
+        let mut min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for p in self.vertices.chunks_exact(3) {
+            let p = glm::vec3(p[0], p[1], p[2]);
+            min = glm::min2(&min, &p);
+            max = glm::max2(&max, &p);
+        }
+        (min, max)
+    }
+
+    // Sphere around the bounding box, as (center, radius)
+    pub fn bounding_sphere(&self) -> (glm::Vec3, f32) {
+        let (min, max) = self.bounding_box();
+        let center = 0.5 * (min + max);
+        let radius = self.vertices.chunks_exact(3)
+            .map(|p| glm::distance(&center, &glm::vec3(p[0], p[1], p[2])))
+            .fold(0.0, f32::max);
+        (center, radius)
+    }
+}
+
+// Lunar terrain
+
+pub struct Terrain;
+impl Terrain {
+    pub fn load(path: &str) -> Mesh {
+        println!("Loading terrain model...");
+        let before = std::time::Instant::now();
+        let models = cache::load_obj(path).expect("Failed to load terrain model");
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+
+        if models.len() != 1 {
+            panic!("Please use a model with a single mesh!")
+            // You could try merging the vertices and indices
+            // of the separate meshes into a single mesh.
+            // I'll leave that as an optional exercise. ;)
+        }
+
+        let terrain = models[0].to_owned();
+        println!("Loaded {} with {} points and {} triangles.",
+            terrain.name,
+            terrain.mesh.positions.len() /3,
+            terrain.mesh.indices.len() / 3,
+        );
+
+        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+    }
+}
@@ -0,0 +1,256 @@
+// Binary cache of parsed OBJ files, written next to the source file after parsing it once.
+//
+// Layout, all little endian:
+//   magic           8 bytes, "GLOOMMSH"
+//   version         u32
+//   source length   u64
+//   source checksum u64
+//   model count     u32
+//   per model:
+//     name length u32, name bytes, padded to a multiple of 4
+//     lengths of positions, vertex colors, normals, texcoords and indices, 5 x u32
+//     positions, vertex colors, normals, texcoords as f32 and indices as u32
+//
+// The cache is memory mapped when read, and ignored when its version or the checksum of the source
+// file do not match, in which case the source is parsed again and the cache rewritten.
+
+use crate::util::checksum;
+use memmap2::Mmap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const MAGIC: &[u8; 8] = b"GLOOMMSH";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    NotACache,
+    UnsupportedVersion(u32),
+    SourceChanged,
+    Truncated,
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CacheError::Io(e)                   => write!(f, "{}", e),
+            CacheError::NotACache               => write!(f, "not a mesh cache"),
+            CacheError::UnsupportedVersion(v)   => write!(f, "unsupported version {}, expected {}", v, VERSION),
+            CacheError::SourceChanged           => write!(f, "source file has changed"),
+            CacheError::Truncated               => write!(f, "file is truncated"),
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
+
+fn cache_path(source_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.cache", source_path))
+}
+
+// Length and checksum of the source file
+fn fingerprint(source_path: &str) -> Result<(u64, u64), CacheError> {
+    let file = File::open(source_path)?;
+    let length = file.metadata()?.len();
+    if length == 0 {
+        return Ok((0, checksum(&[])));
+    }
+    let source = unsafe { Mmap::map(&file)? };
+    Ok((length, checksum(&source)))
+}
+
+struct Reader<'a> {
+    data   : &'a [u8],
+    offset : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], CacheError> {
+        let end = self.offset.checked_add(n).ok_or(CacheError::Truncated)?;
+        let bytes = self.data.get(self.offset..end).ok_or(CacheError::Truncated)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn u32s(&mut self, n: usize) -> Result<Vec<u32>, CacheError> {
+        let bytes = self.bytes(n.checked_mul(4).ok_or(CacheError::Truncated)?)?;
+        Ok(bytes.chunks_exact(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect())
+    }
+
+    fn f32s(&mut self, n: usize) -> Result<Vec<f32>, CacheError> {
+        Ok(self.u32s(n)?.into_iter().map(f32::from_bits).collect())
+    }
+}
+
+// Reads the cache of `source_path`, if it exists and is up to date
+pub fn read(source_path: &str) -> Result<Vec<tobj::Model>, CacheError> {
+    let file = File::open(cache_path(source_path))?;
+    let data = unsafe { Mmap::map(&file)? };
+    let mut reader = Reader { data: &data, offset: 0 };
+
+    if reader.bytes(MAGIC.len()).map_err(|_| CacheError::NotACache)? != MAGIC {
+        return Err(CacheError::NotACache);
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(CacheError::UnsupportedVersion(version));
+    }
+    if (reader.u64()?, reader.u64()?) != fingerprint(source_path)? {
+        return Err(CacheError::SourceChanged);
+    }
+
+    let model_count = reader.u32()?;
+    let mut models = Vec::with_capacity(model_count as usize);
+    for _ in 0..model_count {
+        let name_length = reader.u32()? as usize;
+        let name = String::from_utf8_lossy(reader.bytes(name_length)?).to_string();
+        reader.bytes((4 - name_length % 4) % 4)?;
+
+        let lengths = reader.u32s(5)?;
+        let mesh = tobj::Mesh {
+            positions: reader.f32s(lengths[0] as usize)?,
+            vertex_color: reader.f32s(lengths[1] as usize)?,
+            normals: reader.f32s(lengths[2] as usize)?,
+            texcoords: reader.f32s(lengths[3] as usize)?,
+            indices: reader.u32s(lengths[4] as usize)?,
+            ..Default::default()
+        };
+        models.push(tobj::Model::new(mesh, name));
+    }
+    Ok(models)
+}
+
+// Writes the cache of `source_path`. It is written to a temporary file first and then moved in
+// place, so a cache is never left half written.
+pub fn write(source_path: &str, models: &[tobj::Model]) -> Result<(), CacheError> {
+    let (length, checksum) = fingerprint(source_path)?;
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&length.to_le_bytes());
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data.extend_from_slice(&(models.len() as u32).to_le_bytes());
+    for model in models {
+        let name = model.name.as_bytes();
+        data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        data.extend_from_slice(name);
+        data.resize(data.len() + (4 - name.len() % 4) % 4, 0);
+
+        let mesh = &model.mesh;
+        let floats = [&mesh.positions, &mesh.vertex_color, &mesh.normals, &mesh.texcoords];
+        for array in floats.iter() {
+            data.extend_from_slice(&(array.len() as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&(mesh.indices.len() as u32).to_le_bytes());
+        for value in floats.iter().flat_map(|array| array.iter()) {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in &mesh.indices {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+
+    let path = cache_path(source_path);
+    let temporary = path.with_extension("cache.tmp");
+    File::create(&temporary)?.write_all(&data)?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+// Loads a triangulated, single index OBJ file, from its cache when possible
+pub fn load_obj(path: &str) -> Result<Vec<tobj::Model>, tobj::LoadError> {
+    match read(path) {
+        Ok(models) => return Ok(models),
+        Err(CacheError::Io(e)) if e.kind() == io::ErrorKind::NotFound => { },
+        Err(e) => println!("Ignoring mesh cache of {}: {}", path, e),
+    }
+
+    let (models, _materials) = tobj::load_obj(path,
+        &tobj::LoadOptions{
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        }
+    )?;
+    if let Err(e) = write(path, &models) {
+        println!("Could not write mesh cache of {}: {}", path, e);
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "o quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\n";
+
+    // An OBJ file of its own for each test, in the temporary directory, removed with its cache on drop
+    struct Source(String);
+
+    impl Source {
+        fn new(name: &str, contents: &str) -> Source {
+            let path = std::env::temp_dir().join(format!("gloom-{}-{}.obj", name, std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            Source(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(cache_path(&self.0));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = Source::new("round-trip", QUAD);
+        let models = load_obj(&source.0).unwrap();
+        assert!(cache_path(&source.0).exists());
+
+        let cached = read(&source.0).unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].name, "quad");
+        assert_eq!(cached[0].mesh.positions, models[0].mesh.positions);
+        assert_eq!(cached[0].mesh.normals, models[0].mesh.normals);
+        assert_eq!(cached[0].mesh.indices, models[0].mesh.indices);
+        assert_eq!(cached[0].mesh.indices.len(), 6);
+    }
+
+    #[test]
+    fn stale_checksum_is_rejected() {
+        let source = Source::new("stale", QUAD);
+        load_obj(&source.0).unwrap();
+        // Same length, different contents
+        std::fs::write(&source.0, QUAD.replace("v 1 1 0", "v 2 2 0")).unwrap();
+        assert!(matches!(read(&source.0), Err(CacheError::SourceChanged)));
+
+        let reloaded = load_obj(&source.0).unwrap();
+        assert!(reloaded[0].mesh.positions.contains(&2.0));
+        assert!(read(&source.0).is_ok());
+    }
+
+    #[test]
+    fn truncated_cache_is_rejected() {
+        let source = Source::new("truncated", QUAD);
+        load_obj(&source.0).unwrap();
+        let data = std::fs::read(cache_path(&source.0)).unwrap();
+        std::fs::write(cache_path(&source.0), &data[..data.len() - 4]).unwrap();
+        assert!(matches!(read(&source.0), Err(CacheError::Truncated)));
+    }
+}
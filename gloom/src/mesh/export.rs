@@ -0,0 +1,226 @@
+// Export of meshes to Wavefront OBJ files, to inspect them in Blender or other modelling tools.
+//
+// OBJ has no per vertex colors, so the colors go into an accompanying MTL file instead: every
+// distinct color becomes a material, and each triangle uses the material of its first vertex. The
+// meshes here have one color per part, so nothing is lost in practice.
+
+use super::Mesh;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Collects any number of meshes, each with its own transformation, into one OBJ file
+#[derive(Default)]
+pub struct ObjExport {
+    obj             : Vec<u8>,
+    materials       : Vec<[u8; 4]>,
+    material_ids    : HashMap<[u8; 4], usize>,
+    vertex_count    : usize, // Written so far, to number the vertices of the next mesh
+    normal_count    : usize,
+    texcoord_count  : usize,
+}
+
+fn material_name(color: &[u8; 4]) -> String {
+    format!("color_{:02x}{:02x}{:02x}{:02x}", color[0], color[1], color[2], color[3])
+}
+
+impl ObjExport {
+    // Adds a mesh as an object called `name`, with its positions and normals transformed by `transformation`
+    pub fn add(&mut self, name: &str, mesh: &Mesh, transformation: &glm::Mat4) -> io::Result<()> {
+        let normal_matrix = glm::inverse_transpose(glm::mat4_to_mat3(transformation));
+        let vertex_count = mesh.vertices.len() / 3;
+        let has_normals = mesh.normals.len() == mesh.vertices.len();
+        let has_texcoords = mesh.texcoords.len() == vertex_count * 2;
+        let obj = &mut self.obj;
+
+        writeln!(obj, "o {}", name.replace(char::is_whitespace, "_"))?;
+        for p in mesh.vertices.chunks_exact(3) {
+            let p = transformation * glm::vec4(p[0], p[1], p[2], 1.0);
+            writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        if has_normals {
+            for n in mesh.normals.chunks_exact(3) {
+                let n = normal_matrix * glm::vec3(n[0], n[1], n[2]);
+                let n = if glm::length2(&n) > 0.0 { glm::normalize(&n) } else { n };
+                writeln!(obj, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+        }
+        if has_texcoords {
+            for t in mesh.texcoords.chunks_exact(2) {
+                writeln!(obj, "vt {} {}", t[0], t[1])?;
+            }
+        }
+
+        // Faces grouped by material, so each group needs only one `usemtl`
+        let mut groups: Vec<(usize, Vec<&[u32]>)> = vec![];
+        for t in mesh.indices.chunks_exact(3) {
+            let v = t[0] as usize;
+            let color = match mesh.colors.get(v * 4..v * 4 + 4) {
+                Some(c) => [c[0], c[1], c[2], c[3]].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+                None => [255; 4],
+            };
+            let materials = &mut self.materials;
+            let material = *self.material_ids.entry(color).or_insert_with(|| {
+                materials.push(color);
+                materials.len() - 1
+            });
+            match groups.iter_mut().find(|(m, _)| *m == material) {
+                Some((_, triangles)) => triangles.push(t),
+                None => groups.push((material, vec![t])),
+            }
+        }
+
+        // Indices in OBJ files start at 1, and count the vertices of every object before this one
+        let vertex_offset = self.vertex_count + 1;
+        let normal_offset = self.normal_count + 1;
+        let texcoord_offset = self.texcoord_count + 1;
+        for (material, triangles) in groups {
+            writeln!(obj, "usemtl {}", material_name(&self.materials[material]))?;
+            for t in triangles {
+                write!(obj, "f")?;
+                for &v in t {
+                    let v = v as usize;
+                    match (has_texcoords, has_normals) {
+                        (true, true)   => write!(obj, " {}/{}/{}", v + vertex_offset, v + texcoord_offset, v + normal_offset)?,
+                        (true, false)  => write!(obj, " {}/{}", v + vertex_offset, v + texcoord_offset)?,
+                        (false, true)  => write!(obj, " {}//{}", v + vertex_offset, v + normal_offset)?,
+                        (false, false) => write!(obj, " {}", v + vertex_offset)?,
+                    }
+                }
+                writeln!(obj)?;
+            }
+        }
+
+        self.vertex_count += vertex_count;
+        if has_normals {
+            self.normal_count += vertex_count;
+        }
+        if has_texcoords {
+            self.texcoord_count += vertex_count;
+        }
+        Ok(())
+    }
+
+    // Writes the OBJ file and the MTL file next to it, with the same name but the .mtl extension
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mtl_path = Path::new(path).with_extension("mtl");
+        let mtl_name = mtl_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let mut obj = BufWriter::new(File::create(path)?);
+        let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+        self.write(&mut obj, &mut mtl, mtl_name)?;
+        obj.flush()?;
+        mtl.flush()
+    }
+
+    // Writes the OBJ file to `obj` and the MTL file, which the OBJ file refers to as `mtl_name`, to `mtl`
+    pub fn write(&self, obj: &mut impl Write, mtl: &mut impl Write, mtl_name: &str) -> io::Result<()> {
+        writeln!(obj, "# Exported by gloom-rs")?;
+        writeln!(obj, "mtllib {}", mtl_name)?;
+        obj.write_all(&self.obj)?;
+
+        for color in &self.materials {
+            let [r, g, b, a] = color.map(|c| c as f32 / 255.0);
+            writeln!(mtl, "newmtl {}", material_name(color))?;
+            writeln!(mtl, "Kd {} {} {}", r, g, b)?;
+            writeln!(mtl, "d {}", a)?;
+            writeln!(mtl)?;
+        }
+        Ok(())
+    }
+}
+
+impl Mesh {
+    // Writes the mesh to an OBJ file, with its colors in an MTL file next to it
+    pub fn write_obj(&self, path: &str) -> io::Result<()> {
+        let mut export = ObjExport::default();
+        export.add("mesh", self, &glm::identity())?;
+        export.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle, textured, in red
+    fn textured_triangle() -> Mesh {
+        Mesh {
+            vertices    : vec![0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0],
+            normals     : vec![0.0, 0.0, 1.0,  0.0, 0.0, 1.0,  0.0, 0.0, 1.0],
+            colors      : [[1.0, 0.0, 0.0, 1.0]; 3].concat(),
+            texcoords   : vec![0.0, 0.0,  1.0, 0.0,  0.0, 1.0],
+            indices     : vec![0, 1, 2],
+            index_count : 3,
+        }
+    }
+
+    // Two triangles without texture coordinates, the first in blue and the second in red
+    fn untextured_quad() -> Mesh {
+        Mesh {
+            vertices    : vec![0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  1.0, 1.0, 0.0,  0.0, 1.0, 0.0],
+            normals     : [[1.0, 1.0, 0.0]; 4].concat(),
+            colors      : [[0.0, 0.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 1.0]].concat(),
+            texcoords   : vec![],
+            indices     : vec![0, 1, 2,  2, 3, 0],
+            index_count : 6,
+        }
+    }
+
+    fn export() -> (String, String) {
+        let mut export = ObjExport::default();
+        export.add("first triangle", &textured_triangle(), &glm::identity()).unwrap();
+        let stretch = glm::scaling(&glm::vec3(2.0, 1.0, 1.0));
+        export.add("quad", &untextured_quad(), &stretch).unwrap();
+        let (mut obj, mut mtl) = (vec![], vec![]);
+        export.write(&mut obj, &mut mtl, "scene.mtl").unwrap();
+        (String::from_utf8(obj).unwrap(), String::from_utf8(mtl).unwrap())
+    }
+
+    fn lines<'a>(text: &'a str, keyword: &str) -> Vec<&'a str> {
+        text.lines().filter(|line| line.split_whitespace().next() == Some(keyword)).collect()
+    }
+
+    #[test]
+    fn faces_count_the_vertices_of_earlier_objects() {
+        let (obj, _) = export();
+        assert_eq!(lines(&obj, "o"), ["o first_triangle", "o quad"]);
+        assert_eq!(lines(&obj, "v").len(), 7);
+        assert_eq!(lines(&obj, "vn").len(), 7);
+        assert_eq!(lines(&obj, "vt").len(), 3);
+        assert_eq!(lines(&obj, "f"), [
+            "f 1/1/1 2/2/2 3/3/3",
+            "f 4//4 5//5 6//6",
+            "f 6//6 7//7 4//4",
+        ]);
+        assert_eq!(lines(&obj, "v")[5], "v 2 1 0");
+    }
+
+    #[test]
+    fn faces_use_the_material_of_their_first_vertex() {
+        let (obj, mtl) = export();
+        assert_eq!(lines(&obj, "mtllib"), ["mtllib scene.mtl"]);
+        assert_eq!(lines(&obj, "usemtl"), [
+            "usemtl color_ff0000ff",
+            "usemtl color_0000ffff",
+            "usemtl color_ff0000ff",
+        ]);
+        assert_eq!(lines(&mtl, "newmtl"), ["newmtl color_ff0000ff", "newmtl color_0000ffff"]);
+        assert_eq!(lines(&mtl, "Kd"), ["Kd 1 0 0", "Kd 0 0 1"]);
+    }
+
+    #[test]
+    fn normals_are_transformed_by_the_inverse_transpose() {
+        let (obj, _) = export();
+        let normals: Vec<glm::Vec3> = lines(&obj, "vn").iter().map(|line| {
+            let n: Vec<f32> = line.split_whitespace().skip(1).map(|c| c.parse().unwrap()).collect();
+            glm::vec3(n[0], n[1], n[2])
+        }).collect();
+        assert_eq!(normals[0], glm::vec3(0.0, 0.0, 1.0));
+        // Stretching along X flattens the surface, so its normal leans towards Y
+        let expected = glm::normalize(&glm::vec3(0.5, 1.0, 0.0));
+        for n in &normals[3..] {
+            assert!(glm::distance(n, &expected) < 1e-6, "{} is not {}", n, expected);
+        }
+    }
+}
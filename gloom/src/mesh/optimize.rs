@@ -0,0 +1,408 @@
+// Vertex welding and index/vertex buffer reordering for faster rendering.
+//
+// The post-transform vertex cache lets the GPU skip re-running the vertex shader for recently used
+// vertices, so triangle orders that reuse vertices soon after each other render faster. How well an
+// order does is measured by the average cache miss ratio (ACMR), the number of vertex shader
+// invocations per triangle: 3.0 is the worst case, around 0.5 to 0.7 is the best a regular mesh allows.
+
+use super::Mesh;
+use std::collections::HashMap;
+
+// Size of the LRU cache assumed when ordering triangles, following Forsyth's recommendation
+const VERTEX_CACHE_SIZE: usize = 32;
+
+// Size of the FIFO cache used to measure the result, which is closer to how actual hardware behaves
+const MEASURED_CACHE_SIZE: usize = 16;
+
+// Overdraw ordering may raise the ACMR of a cluster of triangles up to this factor
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+pub struct OptimizationStats {
+    pub acmr_before     : f32, // vertex shader invocations per triangle
+    pub acmr_after      : f32,
+    pub atvr_before     : f32, // vertex shader invocations per vertex, 1.0 is optimal
+    pub atvr_after      : f32,
+}
+
+impl std::fmt::Display for OptimizationStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+            self.acmr_before, self.acmr_after, self.atvr_before, self.atvr_after)
+    }
+}
+
+// Vertex cache misses of drawing the indices in order with a FIFO cache of the given size
+fn cache_misses(indices: &[u32], cache_size: usize) -> usize {
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &i in indices {
+        if !cache.contains(&i) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(i);
+        }
+    }
+    misses
+}
+
+// Forsyth's vertex score, favouring vertices in the cache and vertices with few triangles left
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices get a fixed score, so its neighbours are not preferred too strongly
+        Some(p) if p < 3 => 0.75,
+        Some(p) => (1.0 - (p - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    cache_score + 2.0 * (remaining_triangles as f32).powf(-0.5)
+}
+
+impl Mesh {
+    fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    // Average cache miss ratio of the current index order with a FIFO cache of `cache_size` vertices
+    pub fn acmr(&self, cache_size: usize) -> f32 {
+        cache_misses(&self.indices, cache_size) as f32 / (self.indices.len() / 3).max(1) as f32
+    }
+
+    // Average transformed vertex ratio, the same cache misses relative to the number of vertices
+    pub fn atvr(&self, cache_size: usize) -> f32 {
+        cache_misses(&self.indices, cache_size) as f32 / self.vertex_count().max(1) as f32
+    }
+
+    // Merges vertices whose positions, normals, colors and texture coordinates all lie within
+    // `epsilon` of each other, then drops triangles which collapsed. Returns how many vertices were
+    // removed. An epsilon of zero only merges exact duplicates.
+    pub fn weld(&mut self, epsilon: f32) -> usize {
+        let vertex_count = self.vertex_count();
+        let attributes: Vec<(&Vec<f32>, usize)> = [(&self.vertices, 3), (&self.normals, 3), (&self.colors, 4), (&self.texcoords, 2)]
+            .iter()
+            .cloned()
+            .filter(|(data, width)| data.len() == vertex_count * width)
+            .collect();
+        let matches = |a: usize, b: usize| attributes.iter().all(|(data, width)| {
+            (0..*width).all(|c| (data[a * width + c] - data[b * width + c]).abs() <= epsilon)
+        });
+
+        // Positions are hashed onto a grid of cells `epsilon` wide, so only neighbouring cells need to be
+        // searched. Exact duplicates hash their bits, with -0.0 as 0.0 since the two compare equal.
+        let cell = |x: f32| match epsilon > 0.0 {
+            true => (x / epsilon).floor() as i64,
+            false => if x == 0.0 { 0 } else { x.to_bits() as i64 },
+        };
+        let reach = if epsilon > 0.0 { 1 } else { 0 };
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        let mut remap = vec![0u32; vertex_count];
+        let mut kept = vec![];
+        for (v, target) in remap.iter_mut().enumerate() {
+            let key = (cell(self.vertices[v * 3]), cell(self.vertices[v * 3 + 1]), cell(self.vertices[v * 3 + 2]));
+            let mut found = None;
+            'search: for dx in -reach..=reach {
+                for dy in -reach..=reach {
+                    for dz in -reach..=reach {
+                        let candidates = match grid.get(&(key.0 + dx, key.1 + dy, key.2 + dz)) {
+                            Some(candidates) => candidates,
+                            None => continue,
+                        };
+                        if let Some(&k) = candidates.iter().find(|&&k| matches(kept[k], v)) {
+                            found = Some(k);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            *target = match found {
+                Some(k) => k as u32,
+                None => {
+                    grid.entry(key).or_default().push(kept.len());
+                    kept.push(v);
+                    (kept.len() - 1) as u32
+                }
+            };
+        }
+
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for t in self.indices.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| remap[i as usize]);
+            if a != b && b != c && c != a {
+                indices.extend_from_slice(&[a, b, c]);
+            }
+        }
+        self.indices = indices;
+        self.index_count = self.indices.len() as i32;
+        self.reorder_vertices(&kept);
+        vertex_count - kept.len()
+    }
+
+    // Keeps only the vertices in `order`, in that order. Indices must already refer to positions in `order`.
+    fn reorder_vertices(&mut self, order: &[usize]) {
+        let vertex_count = self.vertex_count();
+        for (data, width) in [(&mut self.vertices, 3), (&mut self.normals, 3), (&mut self.colors, 4), (&mut self.texcoords, 2)] {
+            if data.len() == vertex_count * width {
+                *data = order.iter().flat_map(|&v| data[v * width..(v + 1) * width].to_vec()).collect();
+            }
+        }
+    }
+
+    // Reorders the triangles to make good use of the post-transform vertex cache, after Tom Forsyth's
+    // "Linear-Speed Vertex Cache Optimisation" (2006)
+    pub fn optimize_vertex_cache(&mut self) {
+        let triangle_count = self.indices.len() / 3;
+        let mut vertex_triangles = vec![Vec::new(); self.vertex_count()];
+        for (t, triangle) in self.indices.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                vertex_triangles[v as usize].push(t);
+            }
+        }
+        let mut vertex_scores: Vec<f32> = vertex_triangles.iter().map(|t| vertex_score(None, t.len())).collect();
+        let triangle_score = |t: usize, scores: &Vec<f32>| -> f32 {
+            self.indices[t * 3..t * 3 + 3].iter().map(|&v| scores[v as usize]).sum()
+        };
+        let mut triangle_scores: Vec<f32> = (0..triangle_count).map(|t| triangle_score(t, &vertex_scores)).collect();
+        let mut emitted = vec![false; triangle_count];
+
+        let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut next_unemitted = 0;
+        let mut best = None;
+        for _ in 0..triangle_count {
+            // Without a candidate around the cache, fall back to the first triangle not yet emitted
+            let t = match best {
+                Some(t) => t,
+                None => {
+                    while emitted[next_unemitted] {
+                        next_unemitted += 1;
+                    }
+                    next_unemitted
+                }
+            };
+            emitted[t] = true;
+            let triangle = [self.indices[t * 3], self.indices[t * 3 + 1], self.indices[t * 3 + 2]];
+            indices.extend_from_slice(&triangle);
+
+            for &v in triangle.iter().rev() {
+                vertex_triangles[v as usize].retain(|&other| other != t);
+                cache.retain(|&c| c != v);
+                cache.insert(0, v);
+            }
+            let evicted = if cache.len() > VERTEX_CACHE_SIZE { cache.split_off(VERTEX_CACHE_SIZE) } else { vec![] };
+
+            for (position, &v) in cache.iter().enumerate() {
+                vertex_scores[v as usize] = vertex_score(Some(position), vertex_triangles[v as usize].len());
+            }
+            for &v in &evicted {
+                vertex_scores[v as usize] = vertex_score(None, vertex_triangles[v as usize].len());
+            }
+
+            best = None;
+            let mut best_score = f32::MIN;
+            for &v in cache.iter().chain(evicted.iter()) {
+                for &other in &vertex_triangles[v as usize] {
+                    triangle_scores[other] = triangle_score(other, &vertex_scores);
+                    if triangle_scores[other] > best_score {
+                        best_score = triangle_scores[other];
+                        best = Some(other);
+                    }
+                }
+            }
+        }
+        self.indices = indices;
+    }
+
+    // Reorders clusters of triangles so the ones facing outwards are drawn first, which lets early
+    // depth testing reject more of what is behind them. Based on Sander et al., "Fast Triangle
+    // Reordering for Vertex Locality and Reduced Overdraw" (2007). Run this after
+    // `optimize_vertex_cache`, clusters are cut where that order has cache misses anyway.
+    pub fn optimize_overdraw(&mut self, threshold: f32) {
+        let triangle_count = self.indices.len() / 3;
+        if triangle_count == 0 {
+            return;
+        }
+        let global_acmr = self.acmr(MEASURED_CACHE_SIZE);
+
+        // Cut clusters where all three vertices miss the cache, or where the cluster so far is
+        // already about as cache efficient as the whole mesh. The cache is simulated as empty at the
+        // start of every cluster, since any cluster may end up following any other.
+        let mut clusters = vec![0];
+        let mut cache = std::collections::VecDeque::with_capacity(MEASURED_CACHE_SIZE);
+        let mut cluster_misses = 0;
+        for t in 0..triangle_count {
+            let cluster_start = *clusters.last().unwrap();
+            let cluster_size = t - cluster_start;
+            let soft_boundary = cluster_size > 0 && (cluster_misses as f32 / cluster_size as f32) <= global_acmr * threshold;
+            let hard_boundary = cluster_size > 0 && self.indices[t * 3..t * 3 + 3].iter().all(|v| !cache.contains(v));
+            if soft_boundary || hard_boundary {
+                clusters.push(t);
+                cluster_misses = 0;
+                cache.clear();
+            }
+
+            let mut misses = 0;
+            for &v in &self.indices[t * 3..t * 3 + 3] {
+                if !cache.contains(&v) {
+                    misses += 1;
+                    if cache.len() == MEASURED_CACHE_SIZE {
+                        cache.pop_front();
+                    }
+                    cache.push_back(v);
+                }
+            }
+            cluster_misses += misses;
+        }
+        clusters.push(triangle_count);
+
+        let position = |v: u32| glm::vec3(self.vertices[v as usize * 3], self.vertices[v as usize * 3 + 1], self.vertices[v as usize * 3 + 2]);
+        let mut mesh_centroid = glm::Vec3::zeros();
+        let mut mesh_area = 0.0;
+        let mut cluster_keys: Vec<(f32, usize, usize)> = Vec::with_capacity(clusters.len() - 1);
+        let mut cluster_centroids = Vec::with_capacity(clusters.len() - 1);
+        for range in clusters.windows(2) {
+            let mut centroid = glm::Vec3::zeros();
+            let mut normal = glm::Vec3::zeros();
+            let mut area = 0.0;
+            for t in range[0]..range[1] {
+                let [a, b, c] = [self.indices[t * 3], self.indices[t * 3 + 1], self.indices[t * 3 + 2]].map(position);
+                let n = glm::cross(&(b - a), &(c - a));
+                let triangle_area = 0.5 * glm::length(&n);
+                centroid += (a + b + c) * (triangle_area / 3.0);
+                normal += n;
+                area += triangle_area;
+            }
+            mesh_centroid += centroid;
+            mesh_area += area;
+            cluster_centroids.push((if area > 0.0 { centroid / area } else { centroid }, normal));
+            cluster_keys.push((0.0, range[0], range[1]));
+        }
+        if mesh_area > 0.0 {
+            mesh_centroid /= mesh_area;
+        }
+        for (key, (centroid, normal)) in cluster_keys.iter_mut().zip(cluster_centroids) {
+            let length = glm::length(&normal);
+            key.0 = if length > 0.0 { glm::dot(&(centroid - mesh_centroid), &(normal / length)) } else { 0.0 };
+        }
+
+        // The most outward facing clusters first
+        cluster_keys.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        self.indices = cluster_keys.iter()
+            .flat_map(|&(_, start, end)| self.indices[start * 3..end * 3].to_vec())
+            .collect();
+    }
+
+    // Reorders the vertices by first use in the index buffer, so vertex fetches are mostly sequential.
+    // Vertices no triangle refers to are dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertex_count()];
+        let mut order = vec![];
+        for i in self.indices.iter_mut() {
+            if remap[*i as usize] == u32::MAX {
+                remap[*i as usize] = order.len() as u32;
+                order.push(*i as usize);
+            }
+            *i = remap[*i as usize];
+        }
+        self.reorder_vertices(&order);
+    }
+
+    // Runs all of the above reorderings, returning how the vertex cache fares before and after
+    pub fn optimize(&mut self) -> OptimizationStats {
+        let acmr_before = self.acmr(MEASURED_CACHE_SIZE);
+        let atvr_before = self.atvr(MEASURED_CACHE_SIZE);
+        self.optimize_vertex_cache();
+        self.optimize_overdraw(OVERDRAW_THRESHOLD);
+        self.optimize_vertex_fetch();
+        OptimizationStats {
+            acmr_before,
+            acmr_after: self.acmr(MEASURED_CACHE_SIZE),
+            atvr_before,
+            atvr_after: self.atvr(MEASURED_CACHE_SIZE),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A grid of `n` by `n` quads in the xy plane, two triangles each, with its own colors
+    fn grid(n: u32) -> Mesh {
+        let side = n + 1;
+        let vertices: Vec<f32> = (0..side * side).flat_map(|v| [(v % side) as f32, (v / side) as f32, 0.0]).collect();
+        let mut indices = vec![];
+        for y in 0..n {
+            for x in 0..n {
+                let v = y * side + x;
+                indices.extend_from_slice(&[v, v + 1, v + side, v + 1, v + side + 1, v + side]);
+            }
+        }
+        Mesh {
+            normals: [0.0, 0.0, 1.0].repeat(vertices.len() / 3),
+            colors: [1.0; 4].repeat(vertices.len() / 3),
+            texcoords: vec![],
+            vertices,
+            index_count: indices.len() as i32,
+            indices,
+        }
+    }
+
+    // Every triangle with vertices of its own, as a mesh loaded without shared vertices would be
+    fn unshared(mesh: &Mesh) -> Mesh {
+        let order: Vec<usize> = mesh.indices.iter().map(|&i| i as usize).collect();
+        let mut soup = mesh.clone();
+        soup.indices = (0..order.len() as u32).collect();
+        soup.reorder_vertices(&order);
+        soup
+    }
+
+    #[test]
+    fn weld_merges_duplicates() {
+        let mesh = grid(4);
+        let mut soup = unshared(&mesh);
+        assert_eq!(soup.vertex_count(), 96);
+        assert_eq!(soup.weld(0.0), 96 - 25);
+        assert_eq!(soup.vertex_count(), 25);
+        assert_eq!(soup.indices.len(), mesh.indices.len());
+    }
+
+    #[test]
+    fn weld_keeps_vertices_apart_beyond_epsilon() {
+        let mut mesh = unshared(&grid(1));
+        mesh.colors[4] = 0.5;
+        // The second vertex differs in color from its duplicate in the second triangle
+        assert_eq!(mesh.weld(0.0), 1);
+        assert_eq!(mesh.weld(0.6), 1);
+    }
+
+    #[test]
+    fn weld_merges_signed_zeros() {
+        let mut mesh = unshared(&grid(1));
+        for (i, x) in mesh.vertices.iter_mut().enumerate() {
+            if *x == 0.0 && i % 2 == 0 {
+                *x = -0.0;
+            }
+        }
+        assert_eq!(mesh.weld(0.0), 2);
+        assert_eq!(mesh.vertex_count(), 4);
+    }
+
+    #[test]
+    fn forsyth_lowers_acmr() {
+        let mut mesh = grid(32);
+        // Scatter the triangles, 1001 being coprime to the 2048 triangles
+        let triangle_count = mesh.indices.len() / 3;
+        mesh.indices = (0..triangle_count)
+            .flat_map(|t| { let s = t * 1001 % triangle_count; mesh.indices[s * 3..s * 3 + 3].to_vec() })
+            .collect();
+        let before = mesh.acmr(MEASURED_CACHE_SIZE);
+        mesh.optimize_vertex_cache();
+        let after = mesh.acmr(MEASURED_CACHE_SIZE);
+        assert_eq!(mesh.indices.len(), triangle_count * 3);
+        assert!(before > 2.0, "{}", before);
+        assert!(after < 0.8, "{} -> {}", before, after);
+    }
+}
@@ -0,0 +1,273 @@
+// Ray casting against meshes, accelerated by a bounding volume hierarchy over the triangles.
+//
+// The hierarchy keeps its own copy of the triangle corners, so it can outlive the mesh it was built
+// from and be shared by every node drawing that mesh. It is built by splitting the triangles at the
+// median of their centroids along the longest axis of their bounds, which is quick to build and
+// good enough for the meshes here, whose triangles are all of similar size.
+
+use super::Mesh;
+
+// Triangles per leaf, below which a node is not split any further
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin    : glm::Vec3,
+    pub direction : glm::Vec3, // Not necessarily of unit length, distances are measured in multiples of it
+}
+
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // The same ray in the coordinates given by `matrix`. The direction is not normalized, so
+    // distances along the transformed ray are the same as along this one.
+    pub fn transform(&self, matrix: &glm::Mat4) -> Ray {
+        Ray {
+            origin    : (matrix * glm::vec4(self.origin.x, self.origin.y, self.origin.z, 1.0)).xyz(),
+            direction : (matrix * glm::vec4(self.direction.x, self.direction.y, self.direction.z, 0.0)).xyz(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshHit {
+    pub triangle    : usize,     // Which triangle was hit, as its index in the mesh's indices divided by 3
+    pub point       : glm::Vec3, // Where
+    pub normal      : glm::Vec3, // The unit normal of the triangle, on the side given by its winding
+    pub distance    : f32,       // How far along the ray, in multiples of its direction
+    pub barycentric : glm::Vec2, // Weights of the second and third corners of the triangle at the point
+}
+
+struct BvhNode {
+    min   : glm::Vec3,
+    max   : glm::Vec3,
+    start : usize, // The first triangle of a leaf, or the second child of an inner node, the first is next
+    count : usize, // Triangles in a leaf, 0 for inner nodes
+}
+
+pub struct Bvh {
+    nodes     : Vec<BvhNode>,
+    triangles : Vec<[glm::Vec3; 3]>, // The corners of every triangle, in the order of the leaves
+    ids       : Vec<usize>,          // Which triangle of the mesh each of them is
+}
+
+// Möller-Trumbore, returning the distance along the ray and the barycentric coordinates of the hit
+fn intersect_triangle(ray: &Ray, [a, b, c]: &[glm::Vec3; 3], max_distance: f32) -> Option<(f32, glm::Vec2)> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = glm::cross(&ray.direction, &ac);
+    let determinant = glm::dot(&ab, &p);
+    if determinant.abs() < f32::EPSILON * glm::length2(&ab).max(glm::length2(&ac)) {
+        return None; // parallel to the triangle, or the triangle is degenerate
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = glm::dot(&s, &p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = glm::cross(&s, &ab);
+    let v = glm::dot(&ray.direction, &q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = glm::dot(&ac, &q) * inverse;
+    if distance < 0.0 || distance >= max_distance {
+        return None;
+    }
+    Some((distance, glm::vec2(u, v)))
+}
+
+// Slab test, returning the distance at which the ray enters the box if it does before `max_distance`
+fn intersect_box(origin: &glm::Vec3, inverse_direction: &glm::Vec3, min: &glm::Vec3, max: &glm::Vec3, max_distance: f32) -> Option<f32> {
+    let mut near = 0.0f32;
+    let mut far = max_distance;
+    for i in 0..3 {
+        let t0 = (min[i] - origin[i]) * inverse_direction[i];
+        let t1 = (max[i] - origin[i]) * inverse_direction[i];
+        // NaN from 0 * infinity, when the ray lies in the plane of a face, counts as inside
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    if near <= far { Some(near) } else { None }
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Bvh {
+        let corner = |v: u32| {
+            let i = v as usize * 3;
+            glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+        };
+        let mut bvh = Bvh {
+            nodes     : vec![],
+            triangles : mesh.indices.chunks_exact(3).map(|t| [corner(t[0]), corner(t[1]), corner(t[2])]).collect(),
+            ids       : (0..mesh.indices.len() / 3).collect(),
+        };
+        let centroids: Vec<glm::Vec3> = bvh.triangles.iter().map(|[a, b, c]| (a + b + c) / 3.0).collect();
+        let mut order: Vec<usize> = (0..bvh.triangles.len()).collect();
+        if !order.is_empty() {
+            bvh.build(&centroids, &mut order, 0);
+        }
+
+        bvh.triangles = order.iter().map(|&t| bvh.triangles[t]).collect();
+        bvh.ids = order;
+        bvh
+    }
+
+    // Adds the node covering `order[start..]`, and its children
+    fn build(&mut self, centroids: &[glm::Vec3], order: &mut [usize], start: usize) {
+        let mut min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for &t in order.iter() {
+            for corner in &self.triangles[t] {
+                min = glm::min2(&min, corner);
+                max = glm::max2(&max, corner);
+            }
+        }
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode { min, max, start, count: order.len() });
+        if order.len() <= LEAF_SIZE {
+            return;
+        }
+
+        let mut centroid_min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut centroid_max = -centroid_min;
+        for &t in order.iter() {
+            centroid_min = glm::min2(&centroid_min, &centroids[t]);
+            centroid_max = glm::max2(&centroid_max, &centroids[t]);
+        }
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        if extent[axis] <= 0.0 {
+            return; // all centroids coincide, no split would separate them
+        }
+
+        let middle = order.len() / 2;
+        order.select_nth_unstable_by(middle, |&a, &b| centroids[a][axis].total_cmp(&centroids[b][axis]));
+        let (left, right) = order.split_at_mut(middle);
+        self.nodes[index].count = 0;
+        self.build(centroids, left, start);
+        self.nodes[index].start = self.nodes.len();
+        self.build(centroids, right, start + middle);
+    }
+
+    // The closest hit along the ray, nearer than `max_distance`
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<MeshHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = glm::vec3(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest: Option<(usize, f32, glm::Vec2)> = None;
+        let mut max_distance = max_distance;
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if intersect_box(&ray.origin, &inverse_direction, &node.min, &node.max, max_distance).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for t in node.start..node.start + node.count {
+                    if let Some((distance, barycentric)) = intersect_triangle(ray, &self.triangles[t], max_distance) {
+                        max_distance = distance;
+                        closest = Some((t, distance, barycentric));
+                    }
+                }
+                continue;
+            }
+
+            // Visit the nearer child first, so the farther one is more likely to be culled
+            let (first, second) = (index + 1, node.start);
+            let near = |child: usize| {
+                let child = &self.nodes[child];
+                intersect_box(&ray.origin, &inverse_direction, &child.min, &child.max, max_distance).unwrap_or(f32::INFINITY)
+            };
+            if near(first) <= near(second) {
+                stack.extend_from_slice(&[second, first]);
+            } else {
+                stack.extend_from_slice(&[first, second]);
+            }
+        }
+
+        closest.map(|(t, distance, barycentric)| {
+            let [a, b, c] = &self.triangles[t];
+            MeshHit {
+                triangle : self.ids[t],
+                point    : ray.at(distance),
+                normal   : glm::normalize(&glm::cross(&(b - a), &(c - a))),
+                distance,
+                barycentric,
+            }
+        })
+    }
+}
+
+impl Mesh {
+    pub fn bvh(&self) -> Bvh {
+        Bvh::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PolygonMesh;
+    use super::*;
+
+    // Tests every triangle, in order
+    fn brute_force(mesh: &Mesh, ray: &Ray) -> Option<(usize, f32)> {
+        let corner = |v: u32| glm::vec3(mesh.vertices[v as usize * 3], mesh.vertices[v as usize * 3 + 1], mesh.vertices[v as usize * 3 + 2]);
+        let mut closest = None;
+        let mut max_distance = f32::INFINITY;
+        for (t, triangle) in mesh.indices.chunks_exact(3).enumerate() {
+            if let Some((distance, _)) = intersect_triangle(ray, &[corner(triangle[0]), corner(triangle[1]), corner(triangle[2])], max_distance) {
+                max_distance = distance;
+                closest = Some((t, distance));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn bvh_hit_equals_brute_force() {
+        let sphere = PolygonMesh::cube(2.0).catmull_clark(3).triangulate([1.0; 4]);
+        let bvh = sphere.bvh();
+
+        // Rays from points around the sphere towards points near its center, from a fixed linear congruential sequence
+        let mut state = 12345u32;
+        let mut random = || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut hits = 0;
+        for _ in 0..200 {
+            let origin = glm::vec3(random(), random(), random()) * 3.0;
+            let target = glm::vec3(random(), random(), random()) * 0.8;
+            let ray = Ray::new(origin, target - origin);
+
+            let expected = brute_force(&sphere, &ray);
+            let hit = bvh.raycast(&ray, f32::INFINITY);
+            assert_eq!(hit.map(|hit| hit.triangle), expected.map(|(t, _)| t));
+            if let (Some(hit), Some((_, distance))) = (hit, expected) {
+                assert_eq!(hit.distance, distance);
+                hits += 1;
+            }
+        }
+        assert!(hits > 100, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn hit_stops_at_max_distance() {
+        let cube = PolygonMesh::cube(2.0).triangulate([1.0; 4]);
+        let ray = Ray::new(glm::vec3(0.0, 0.0, -5.0), glm::vec3(0.0, 0.0, 1.0));
+        let hit = cube.bvh().raycast(&ray, f32::INFINITY).unwrap();
+        assert_eq!(hit.distance, 4.0);
+        assert_eq!(hit.normal, glm::vec3(0.0, 0.0, -1.0));
+        assert!(cube.bvh().raycast(&ray, 4.0).is_none());
+    }
+}
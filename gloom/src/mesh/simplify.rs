@@ -0,0 +1,454 @@
+// Quadric error metric edge collapse decimation, after Garland and Heckbert, "Surface
+// Simplification Using Quadric Error Metrics" (1997).
+//
+// Edges are collapsed cheapest first until the target triangle count is reached, and the attributes
+// of the surviving vertex are interpolated along the collapsed edge. Open edges are constrained so
+// the outline of the mesh stays in place. Note that this includes the attribute seams which
+// `single_index` loading produces, as the vertices on either side of a seam are not shared.
+
+use super::Mesh;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// How strongly open edges resist being moved, relative to the surface planes around them
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+// Collapses turning any remaining triangle by more than ~78 degrees are rejected as folds
+const MIN_NORMAL_COSINE: f64 = 0.2;
+
+type Vec3 = [f64; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 { [a[0] - b[0], a[1] - b[1], a[2] - b[2]] }
+fn dot(a: Vec3, b: Vec3) -> f64 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+// Symmetric 4x4 matrix measuring the squared distance to a set of planes, upper triangle row by row
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // The plane through `point` with unit normal `n`
+    fn plane(n: Vec3, point: Vec3, weight: f64) -> Quadric {
+        let [a, b, c] = n;
+        let d = -dot(n, point);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|q| q * weight))
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (q, o) in sum.0.iter_mut().zip(other.0.iter()) {
+            *q += o;
+        }
+        sum
+    }
+
+    fn error(&self, p: Vec3) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = p;
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    // The position of least error, unless the planes do not pin down a single point
+    fn minimizer(&self) -> Option<Vec3> {
+        let q = &self.0;
+        let columns = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let rhs = [-q[3], -q[6], -q[8]];
+        let det3 = |a: Vec3, b: Vec3, c: Vec3| dot(a, cross(b, c));
+
+        let det = det3(columns[0], columns[1], columns[2]);
+        let scale = q[0] + q[4] + q[7];
+        if det.abs() <= 1e-9 * scale * scale * scale {
+            return None;
+        }
+        // Cramer's rule
+        Some([
+            det3(rhs, columns[1], columns[2]) / det,
+            det3(columns[0], rhs, columns[2]) / det,
+            det3(columns[0], columns[1], rhs) / det,
+        ])
+    }
+}
+
+// A planned collapse of `remove` into `keep`, valid as long as neither vertex has changed since
+struct Collapse {
+    cost   : f64,
+    keep   : u32,
+    remove : u32,
+    stamps : (u32, u32),
+}
+
+// BinaryHeap is a max-heap, so the ordering is reversed to pop the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Collapse {}
+
+struct Simplifier {
+    positions        : Vec<Vec3>,
+    normals          : Vec<f32>,
+    colors           : Vec<f32>,
+    texcoords        : Vec<f32>,
+    quadrics         : Vec<Quadric>,
+    boundary         : Vec<bool>,
+    stamps           : Vec<u32>,
+    triangles        : Vec<[u32; 3]>,
+    triangle_alive   : Vec<bool>,
+    vertex_triangles : Vec<Vec<usize>>,
+    live_triangles   : usize,
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh) -> Simplifier {
+        let vertex_count = mesh.vertices.len() / 3;
+        let positions: Vec<Vec3> = mesh.vertices.chunks_exact(3)
+            .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+            .collect();
+        let triangles: Vec<[u32; 3]> = mesh.indices.chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+
+        let mut vertex_triangles = vec![Vec::new(); vertex_count];
+        let mut quadrics = vec![Quadric::default(); vertex_count];
+        let mut edge_uses: HashMap<(u32, u32), usize> = HashMap::new();
+        for (i, t) in triangles.iter().enumerate() {
+            let [p0, p1, p2] = t.map(|v| positions[v as usize]);
+            let n = cross(sub(p1, p0), sub(p2, p0));
+            let double_area = dot(n, n).sqrt();
+            for (j, &v) in t.iter().enumerate() {
+                vertex_triangles[v as usize].push(i);
+                let w = t[(j + 1) % 3];
+                *edge_uses.entry((v.min(w), v.max(w))).or_insert(0) += 1;
+            }
+            if double_area > 0.0 {
+                // Weighted by area, so slivers do not dominate the error
+                let plane = Quadric::plane(n.map(|c| c / double_area), p0, 0.5 * double_area);
+                for &v in t {
+                    quadrics[v as usize] = quadrics[v as usize].add(&plane);
+                }
+            }
+        }
+
+        // Open edges get an additional plane perpendicular to their triangle, holding them in place
+        let mut boundary = vec![false; vertex_count];
+        for t in &triangles {
+            let [p0, p1, p2] = t.map(|v| positions[v as usize]);
+            let face_normal = cross(sub(p1, p0), sub(p2, p0));
+            for j in 0..3 {
+                let (a, b) = (t[j], t[(j + 1) % 3]);
+                if edge_uses[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+                boundary[a as usize] = true;
+                boundary[b as usize] = true;
+                let edge = sub(positions[b as usize], positions[a as usize]);
+                let n = cross(edge, face_normal);
+                let length = dot(n, n).sqrt();
+                if length > 0.0 {
+                    let plane = Quadric::plane(n.map(|c| c / length), positions[a as usize], BOUNDARY_WEIGHT * dot(edge, edge));
+                    quadrics[a as usize] = quadrics[a as usize].add(&plane);
+                    quadrics[b as usize] = quadrics[b as usize].add(&plane);
+                }
+            }
+        }
+
+        let has = |data: &Vec<f32>, width: usize| data.len() == vertex_count * width;
+        Simplifier {
+            positions,
+            normals: if has(&mesh.normals, 3) { mesh.normals.clone() } else { vec![] },
+            colors: if has(&mesh.colors, 4) { mesh.colors.clone() } else { vec![] },
+            texcoords: if has(&mesh.texcoords, 2) { mesh.texcoords.clone() } else { vec![] },
+            quadrics,
+            boundary,
+            stamps: vec![0; vertex_count],
+            triangle_alive: vec![true; triangles.len()],
+            live_triangles: triangles.len(),
+            triangles,
+            vertex_triangles,
+        }
+    }
+
+    fn live_triangles_of(&self, v: u32) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_triangles[v as usize].iter().cloned().filter(move |&t| self.triangle_alive[t])
+    }
+
+    fn neighbours(&self, v: u32) -> Vec<u32> {
+        let mut neighbours: Vec<u32> = self.live_triangles_of(v)
+            .flat_map(|t| self.triangles[t])
+            .filter(|&w| w != v)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn shared_triangles(&self, a: u32, b: u32) -> usize {
+        self.live_triangles_of(a).filter(|&t| self.triangles[t].contains(&b)).count()
+    }
+
+    // Cost, target position and interpolation parameter of collapsing edge (a, b)
+    fn plan(&self, a: u32, b: u32) -> Option<(f64, Vec3, f64)> {
+        let (boundary_a, boundary_b) = (self.boundary[a as usize], self.boundary[b as usize]);
+        let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+        let quadric = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+
+        // Boundary vertices stay where they are unless they slide along their own open edge
+        let mut candidates = vec![];
+        match (boundary_a, boundary_b) {
+            (true, false) => candidates.push((pa, 0.0)),
+            (false, true) => candidates.push((pb, 1.0)),
+            (true, true) if self.shared_triangles(a, b) != 1 => return None,
+            _ => {
+                candidates.push((pa, 0.0));
+                candidates.push((pb, 1.0));
+                candidates.push((lerp(pa, pb, 0.5), 0.5));
+                if let Some(optimal) = quadric.minimizer() {
+                    let edge = sub(pb, pa);
+                    let t = (dot(sub(optimal, pa), edge) / dot(edge, edge)).clamp(0.0, 1.0);
+                    if t.is_finite() {
+                        candidates.push((optimal, t));
+                    }
+                }
+            }
+        }
+
+        candidates.into_iter()
+            .map(|(p, t)| (quadric.error(p).max(0.0), p, t))
+            .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(Ordering::Equal))
+    }
+
+    fn push(&self, heap: &mut BinaryHeap<Collapse>, a: u32, b: u32) {
+        if let Some((cost, _, _)) = self.plan(a, b) {
+            heap.push(Collapse {
+                cost,
+                keep: a,
+                remove: b,
+                stamps: (self.stamps[a as usize], self.stamps[b as usize]),
+            });
+        }
+    }
+
+    // Rejects collapses that would make the surface non-manifold or fold triangles over
+    fn is_legal(&self, a: u32, b: u32, target: Vec3) -> bool {
+        let shared = self.shared_triangles(a, b);
+        let neighbours_b = self.neighbours(b);
+        let common = self.neighbours(a).iter().filter(|v| neighbours_b.binary_search(v).is_ok()).count();
+        if common != shared {
+            return false;
+        }
+
+        for t in self.live_triangles_of(a).chain(self.live_triangles_of(b)) {
+            let triangle = self.triangles[t];
+            if triangle.contains(&a) && triangle.contains(&b) {
+                continue;
+            }
+            let before = triangle.map(|v| self.positions[v as usize]);
+            let after = triangle.map(|v| if v == a || v == b { target } else { self.positions[v as usize] });
+            let n_before = cross(sub(before[1], before[0]), sub(before[2], before[0]));
+            let n_after = cross(sub(after[1], after[0]), sub(after[2], after[0]));
+            let lengths = (dot(n_before, n_before) * dot(n_after, n_after)).sqrt();
+            if lengths <= 0.0 || dot(n_before, n_after) < MIN_NORMAL_COSINE * lengths {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn collapse(&mut self, a: u32, b: u32, target: Vec3, t: f64) {
+        let (ai, bi) = (a as usize, b as usize);
+        self.positions[ai] = target;
+        for (data, width) in [(&mut self.normals, 3), (&mut self.colors, 4), (&mut self.texcoords, 2)] {
+            if data.is_empty() {
+                continue;
+            }
+            for c in 0..width {
+                let (x, y) = (data[ai * width + c], data[bi * width + c]);
+                data[ai * width + c] = x + (y - x) * t as f32;
+            }
+        }
+        self.quadrics[ai] = self.quadrics[ai].add(&self.quadrics[bi]);
+        self.boundary[ai] |= self.boundary[bi];
+
+        for t in std::mem::take(&mut self.vertex_triangles[bi]) {
+            if !self.triangle_alive[t] {
+                continue;
+            }
+            if self.triangles[t].contains(&a) {
+                self.triangle_alive[t] = false;
+                self.live_triangles -= 1;
+            } else {
+                for v in self.triangles[t].iter_mut() {
+                    if *v == b {
+                        *v = a;
+                    }
+                }
+                self.vertex_triangles[ai].push(t);
+            }
+        }
+        let alive = &self.triangle_alive;
+        self.vertex_triangles[ai].retain(|&t| alive[t]);
+        self.stamps[ai] += 1;
+        self.stamps[bi] += 1;
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        let mut heap = BinaryHeap::new();
+        for t in 0..self.triangles.len() {
+            let triangle = self.triangles[t];
+            for j in 0..3 {
+                let (a, b) = (triangle[j], triangle[(j + 1) % 3]);
+                // Every interior edge is seen from both sides, only plan it once
+                if a < b || self.shared_triangles(a, b) == 1 {
+                    self.push(&mut heap, a, b);
+                }
+            }
+        }
+
+        while self.live_triangles > target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (a, b) = (collapse.keep, collapse.remove);
+            if collapse.stamps != (self.stamps[a as usize], self.stamps[b as usize]) {
+                continue; // Outdated, one of the vertices has changed since
+            }
+            let (_, target, t) = match self.plan(a, b) {
+                Some(plan) => plan,
+                None => continue,
+            };
+            if !self.is_legal(a, b, target) {
+                continue;
+            }
+            self.collapse(a, b, target, t);
+            for v in self.neighbours(a) {
+                self.push(&mut heap, a, v);
+            }
+        }
+    }
+
+    // Gathers the surviving triangles into a new mesh, dropping unreferenced vertices
+    fn into_mesh(self) -> Mesh {
+        let mut remap = vec![u32::MAX; self.positions.len()];
+        let mut order = vec![];
+        let mut indices = vec![];
+        for (triangle, _) in self.triangles.iter().zip(&self.triangle_alive).filter(|(_, &alive)| alive) {
+            for &v in triangle {
+                if remap[v as usize] == u32::MAX {
+                    remap[v as usize] = order.len() as u32;
+                    order.push(v as usize);
+                }
+                indices.push(remap[v as usize]);
+            }
+        }
+
+        let gather = |data: &Vec<f32>, width: usize| -> Vec<f32> {
+            if data.is_empty() { return vec![]; }
+            order.iter().flat_map(|&v| data[v * width..(v + 1) * width].iter().cloned()).collect()
+        };
+        let mut normals = gather(&self.normals, 3);
+        for n in normals.chunks_exact_mut(3) {
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if length > 0.0 {
+                n.iter_mut().for_each(|c| *c /= length);
+            }
+        }
+
+        Mesh {
+            vertices: order.iter().flat_map(|&v| self.positions[v].map(|c| c as f32)).collect(),
+            normals,
+            colors: gather(&self.colors, 4),
+            texcoords: gather(&self.texcoords, 2),
+            index_count: indices.len() as i32,
+            indices,
+        }
+    }
+}
+
+impl Mesh {
+    // Decimates the mesh down to roughly `ratio` of its triangles. Collapsing stops early when no
+    // legal collapse is left, so the result can have more triangles than asked for.
+    pub fn simplify(&self, ratio: f32) -> Mesh {
+        let triangle_count = self.indices.len() / 3;
+        let target = (triangle_count as f32 * ratio.clamp(0.0, 1.0)).round() as usize;
+        let mut simplifier = Simplifier::new(self);
+        simplifier.run(target.max(1));
+        simplifier.into_mesh()
+    }
+
+    // Levels of detail at the given ratios of the original triangle count, from finest to coarsest.
+    // Each level is decimated from the previous one, which is faster and keeps the levels consistent.
+    pub fn lod_chain(&self, ratios: &[f32]) -> Vec<Mesh> {
+        let triangle_count = self.indices.len() as f32 / 3.0;
+        let mut levels: Vec<Mesh> = Vec::with_capacity(ratios.len());
+        for &ratio in ratios {
+            let previous = levels.last().unwrap_or(self);
+            let previous_count = previous.indices.len() as f32 / 3.0;
+            let level = if previous_count > 0.0 {
+                previous.simplify(triangle_count * ratio / previous_count)
+            } else {
+                previous.clone()
+            };
+            levels.push(level);
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PolygonMesh;
+    use super::*;
+
+    // A cube of 192 flat triangles, with its edges and corners kept sharp by the subdivision
+    fn tessellated_cube() -> Mesh {
+        let cube = PolygonMesh::cube(2.0).triangulate([1.0; 4]);
+        cube.loop_subdivide(&cube.sharp_edges(0.5), 2)
+    }
+
+    fn triangle_count(mesh: &Mesh) -> usize {
+        mesh.indices.len() / 3
+    }
+
+    #[test]
+    fn simplified_cube_keeps_its_bounds() {
+        let cube = tessellated_cube();
+        let simplified = cube.simplify(0.1);
+        assert_eq!(triangle_count(&cube), 192);
+        // 10% of 192 rounds to 19, and a closed cube needs at least 12
+        assert!((12..=19).contains(&triangle_count(&simplified)), "{} triangles left", triangle_count(&simplified));
+        assert_eq!(simplified.index_count as usize, simplified.indices.len());
+
+        let (min, max) = simplified.bounding_box();
+        assert!(glm::distance(&min, &glm::vec3(-1.0, -1.0, -1.0)) < 1e-5, "{:?}", min);
+        assert!(glm::distance(&max, &glm::vec3(1.0, 1.0, 1.0)) < 1e-5, "{:?}", max);
+        assert!(simplified.indices.iter().all(|&i| (i as usize) < simplified.vertices.len() / 3));
+    }
+
+    #[test]
+    fn lod_chain_is_monotonic() {
+        let sphere = PolygonMesh::cube(2.0).catmull_clark(3).triangulate([1.0; 4]);
+        let levels = sphere.lod_chain(&[0.5, 0.25, 0.1]);
+        assert_eq!(levels.len(), 3);
+        let mut previous = triangle_count(&sphere);
+        for level in &levels {
+            assert!(triangle_count(level) < previous, "{} then {}", previous, triangle_count(level));
+            previous = triangle_count(level);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+// Subdivision surfaces: Loop subdivision for triangle meshes, and Catmull-Clark subdivision for
+// polygon meshes made mostly of quads, before they are triangulated.
+//
+// Both refine the mesh and move the vertices towards a smooth limit surface. Edges marked as creases
+// stay sharp: vertices on them only follow the crease, and vertices where more than two creases meet
+// stay where they are. Open edges always act as creases.
+//
+// Meshes loaded with `single_index` split vertices wherever an attribute differs, so the topology
+// is taken from the positions instead. Vertices sharing a position move together, which keeps the
+// surface closed across attribute seams, while attributes are interpolated within each side.
+
+use super::Mesh;
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+type Edge = (usize, usize);
+
+fn edge(a: usize, b: usize) -> Edge {
+    (a.min(b), a.max(b))
+}
+
+fn vec3(data: &[f32], i: usize) -> glm::Vec3 {
+    glm::vec3(data[i * 3], data[i * 3 + 1], data[i * 3 + 2])
+}
+
+// Position topology of a polygon mesh: which faces are on each edge, and the neighbours of each vertex
+struct Topology {
+    edge_faces : HashMap<Edge, Vec<usize>>,
+    neighbours : Vec<Vec<usize>>,
+}
+
+impl Topology {
+    fn new(vertex_count: usize, faces: &[Vec<usize>]) -> Topology {
+        let mut edge_faces: HashMap<Edge, Vec<usize>> = HashMap::new();
+        let mut neighbours = vec![Vec::new(); vertex_count];
+        for (f, face) in faces.iter().enumerate() {
+            for (j, &a) in face.iter().enumerate() {
+                let b = face[(j + 1) % face.len()];
+                let uses = edge_faces.entry(edge(a, b)).or_default();
+                if uses.is_empty() {
+                    neighbours[a].push(b);
+                    neighbours[b].push(a);
+                }
+                uses.push(f);
+            }
+        }
+        Topology { edge_faces, neighbours }
+    }
+
+    // Open and non-manifold edges are treated as creases too
+    fn is_sharp(&self, creases: &HashSet<Edge>, e: Edge) -> bool {
+        creases.contains(&e) || self.edge_faces.get(&e).is_none_or(|faces| faces.len() != 2)
+    }
+
+    // Neighbours along sharp edges
+    fn sharp_neighbours(&self, creases: &HashSet<Edge>, v: usize) -> Vec<usize> {
+        self.neighbours[v].iter().cloned().filter(|&w| self.is_sharp(creases, edge(v, w))).collect()
+    }
+}
+
+// Splits every crease in two at its new midpoint
+fn split_creases(creases: &HashSet<Edge>, midpoints: &HashMap<Edge, usize>) -> HashSet<Edge> {
+    creases.iter()
+        .filter_map(|&(a, b)| midpoints.get(&(a, b)).map(|&m| [edge(a, m), edge(m, b)]))
+        .flatten()
+        .collect()
+}
+
+// One level of Loop subdivision on positions, returning the new positions, the midpoint of every
+// edge and the new creases. The positions of the original vertices keep their indices.
+fn loop_positions(positions: &[glm::Vec3], triangles: &[Vec<usize>], creases: &HashSet<Edge>)
+    -> (Vec<glm::Vec3>, HashMap<Edge, usize>, HashSet<Edge>)
+{
+    let topology = Topology::new(positions.len(), triangles);
+    let mut new_positions = Vec::with_capacity(positions.len() + topology.edge_faces.len());
+
+    for (v, &p) in positions.iter().enumerate() {
+        let sharp = topology.sharp_neighbours(creases, v);
+        let neighbours = &topology.neighbours[v];
+        new_positions.push(match sharp.len() {
+            0 | 1 if !neighbours.is_empty() => {
+                let n = neighbours.len() as f32;
+                let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+                p * (1.0 - n * beta) + neighbours.iter().map(|&w| positions[w]).sum::<glm::Vec3>() * beta
+            }
+            2 => p * 0.75 + (positions[sharp[0]] + positions[sharp[1]]) * 0.125,
+            _ => p, // corner
+        });
+    }
+
+    let mut midpoints = HashMap::with_capacity(topology.edge_faces.len());
+    for (&(a, b), faces) in &topology.edge_faces {
+        let point = if topology.is_sharp(creases, (a, b)) {
+            (positions[a] + positions[b]) * 0.5
+        } else {
+            let opposite: glm::Vec3 = faces.iter()
+                .map(|&f| triangles[f].iter().find(|&&v| v != a && v != b).map_or(glm::Vec3::zeros(), |&v| positions[v]))
+                .sum();
+            (positions[a] + positions[b]) * 0.375 + opposite * 0.125
+        };
+        midpoints.insert((a, b), new_positions.len());
+        new_positions.push(point);
+    }
+
+    let creases = split_creases(creases, &midpoints);
+    (new_positions, midpoints, creases)
+}
+
+impl Mesh {
+    // Edges where the faces on either side meet at more than `angle` radians, as pairs of vertex
+    // indices. Useful as creases, to keep the hard edges of a low-poly model while smoothing the rest.
+    pub fn sharp_edges(&self, angle: f32) -> Vec<(u32, u32)> {
+        let (position_of, positions) = self.position_ids();
+        let mut edge_normals: HashMap<Edge, (Vec<glm::Vec3>, (u32, u32))> = HashMap::new();
+        for t in self.indices.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|v| positions[position_of[v as usize]]);
+            let n = glm::cross(&(b - a), &(c - a));
+            if glm::length2(&n) == 0.0 {
+                continue;
+            }
+            for j in 0..3 {
+                let (u, v) = (t[j], t[(j + 1) % 3]);
+                let e = edge(position_of[u as usize], position_of[v as usize]);
+                edge_normals.entry(e).or_insert_with(|| (vec![], (u, v))).0.push(glm::normalize(&n));
+            }
+        }
+        edge_normals.values()
+            .filter(|(normals, _)| normals.len() == 2 && glm::dot(&normals[0], &normals[1]) < angle.cos())
+            .map(|(_, e)| *e)
+            .collect()
+    }
+
+    // Vertices grouped by position, as (the position index of every vertex, the distinct positions)
+    fn position_ids(&self) -> (Vec<usize>, Vec<glm::Vec3>) {
+        let mut ids: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut positions = vec![];
+        let position_of = self.vertices.chunks_exact(3).map(|p| {
+            *ids.entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]).or_insert_with(|| {
+                positions.push(glm::vec3(p[0], p[1], p[2]));
+                positions.len() - 1
+            })
+        }).collect();
+        (position_of, positions)
+    }
+
+    // Applies `levels` levels of Loop subdivision, each one splitting every triangle into four.
+    // `creases` are pairs of vertex indices, see `sharp_edges`. Normals are recomputed from the
+    // subdivided surface, smooth across attribute seams but not across creases.
+    pub fn loop_subdivide(&self, creases: &[(u32, u32)], levels: usize) -> Mesh {
+        let (mut position_of, mut positions) = self.position_ids();
+        let mut creases: HashSet<Edge> = creases.iter()
+            .map(|&(a, b)| edge(position_of[a as usize], position_of[b as usize]))
+            .collect();
+        let mut mesh = self.clone();
+
+        for _ in 0..levels {
+            let triangles: Vec<Vec<usize>> = mesh.indices.chunks_exact(3)
+                .map(|t| t.iter().map(|&v| position_of[v as usize]).collect())
+                .collect();
+            let (new_positions, midpoints, new_creases) = loop_positions(&positions, &triangles, &creases);
+
+            // Every edge between two vertices gets its own midpoint vertex, so attributes stay split at seams
+            let vertex_count = mesh.vertices.len() / 3;
+            let mut vertex_midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut indices = Vec::with_capacity(mesh.indices.len() * 4);
+            for t in mesh.indices.clone().chunks_exact(3) {
+                let mut m = [0u32; 3];
+                for j in 0..3 {
+                    let (a, b) = (t[j], t[(j + 1) % 3]);
+                    m[j] = *vertex_midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                        let index = position_of.len() as u32;
+                        position_of.push(midpoints[&edge(position_of[a as usize], position_of[b as usize])]);
+                        for (data, width) in [(&mut mesh.normals, 3), (&mut mesh.colors, 4), (&mut mesh.texcoords, 2)] {
+                            if data.len() >= vertex_count * width {
+                                for c in 0..width {
+                                    data.push(0.5 * (data[a as usize * width + c] + data[b as usize * width + c]));
+                                }
+                            }
+                        }
+                        index
+                    });
+                }
+                indices.extend_from_slice(&[t[0], m[0], m[2], t[1], m[1], m[0], t[2], m[2], m[1], m[0], m[1], m[2]]);
+            }
+
+            mesh.vertices = position_of.iter().flat_map(|&p| new_positions[p].iter().cloned().collect::<Vec<f32>>()).collect();
+            mesh.index_count = indices.len() as i32;
+            mesh.indices = indices;
+            positions = new_positions;
+            creases = new_creases;
+        }
+
+        mesh.recompute_normals(&position_of, &creases);
+        mesh
+    }
+
+    // Area weighted vertex normals, shared by all vertices at the same position unless it lies on a crease
+    fn recompute_normals(&mut self, position_of: &[usize], creases: &HashSet<Edge>) {
+        let vertex_count = self.vertices.len() / 3;
+        let on_crease: HashSet<usize> = creases.iter().flat_map(|&(a, b)| [a, b]).collect();
+        let mut vertex_normals = vec![glm::Vec3::zeros(); vertex_count];
+        let mut position_normals = vec![glm::Vec3::zeros(); position_of.iter().max().map_or(0, |&p| p + 1)];
+        for t in self.indices.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|v| vec3(&self.vertices, v as usize));
+            let n = glm::cross(&(b - a), &(c - a));
+            for &v in t {
+                vertex_normals[v as usize] += n;
+                position_normals[position_of[v as usize]] += n;
+            }
+        }
+
+        self.normals = (0..vertex_count).flat_map(|v| {
+            let p = position_of[v];
+            let n = if on_crease.contains(&p) { vertex_normals[v] } else { position_normals[p] };
+            let n = if glm::length2(&n) > 0.0 { glm::normalize(&n) } else { glm::vec3(0.0, 1.0, 0.0) };
+            [n.x, n.y, n.z]
+        }).collect();
+    }
+}
+
+// A mesh of arbitrary polygons, with positions only, as loaded from an OBJ file without triangulation
+pub struct PolygonMesh {
+    pub positions : Vec<f32>,
+    pub faces     : Vec<Vec<u32>>,
+    pub creases   : Vec<(u32, u32)>, // Pairs of vertex indices, kept sharp when subdividing
+}
+
+impl PolygonMesh {
+    // From a mesh loaded with `triangulate: false` and `single_index: false`
+    pub fn from_obj(mesh: &tobj::Mesh) -> PolygonMesh {
+        let mut faces = vec![];
+        let mut start = 0;
+        if mesh.face_arities.is_empty() {
+            faces = mesh.indices.chunks_exact(3).map(|t| t.to_vec()).collect();
+        } else {
+            for &arity in &mesh.face_arities {
+                faces.push(mesh.indices[start..start + arity as usize].to_vec());
+                start += arity as usize;
+            }
+        }
+        PolygonMesh { positions: mesh.positions.clone(), faces, creases: vec![] }
+    }
+
+    // An axis aligned cube of quads around the origin, which Catmull-Clark turns into a sphere-like shape
+    pub fn cube(size: f32) -> PolygonMesh {
+        let h = 0.5 * size;
+        let positions = (0..8).flat_map(|i| [
+            if i & 1 == 0 { -h } else { h },
+            if i & 2 == 0 { -h } else { h },
+            if i & 4 == 0 { -h } else { h },
+        ]).collect();
+        let faces = vec![
+            vec![0, 2, 3, 1], vec![4, 5, 7, 6], // -z, +z
+            vec![0, 1, 5, 4], vec![2, 6, 7, 3], // -y, +y
+            vec![0, 4, 6, 2], vec![1, 3, 7, 5], // -x, +x
+        ];
+        PolygonMesh { positions, faces, creases: vec![] }
+    }
+
+    // Applies `levels` levels of Catmull-Clark subdivision. After the first level all faces are quads.
+    pub fn catmull_clark(&self, levels: usize) -> PolygonMesh {
+        let mut positions: Vec<glm::Vec3> = (0..self.positions.len() / 3).map(|v| vec3(&self.positions, v)).collect();
+        let mut faces: Vec<Vec<usize>> = self.faces.iter().map(|f| f.iter().map(|&v| v as usize).collect()).collect();
+        let mut creases: HashSet<Edge> = self.creases.iter().map(|&(a, b)| edge(a as usize, b as usize)).collect();
+
+        for _ in 0..levels {
+            let topology = Topology::new(positions.len(), &faces);
+            let face_points: Vec<glm::Vec3> = faces.iter()
+                .map(|f| f.iter().map(|&v| positions[v]).sum::<glm::Vec3>() / f.len() as f32)
+                .collect();
+
+            let mut new_positions = Vec::with_capacity(positions.len() + topology.edge_faces.len() + faces.len());
+            for (v, &p) in positions.iter().enumerate() {
+                let sharp = topology.sharp_neighbours(&creases, v);
+                let neighbours = &topology.neighbours[v];
+                new_positions.push(match sharp.len() {
+                    0 | 1 if !neighbours.is_empty() => {
+                        let adjacent: Vec<usize> = neighbours.iter()
+                            .flat_map(|&w| topology.edge_faces[&edge(v, w)].iter().cloned())
+                            .collect::<HashSet<usize>>()
+                            .into_iter()
+                            .collect();
+                        let n = neighbours.len() as f32;
+                        let f = adjacent.iter().map(|&face| face_points[face]).sum::<glm::Vec3>() / adjacent.len() as f32;
+                        let r = neighbours.iter().map(|&w| (p + positions[w]) * 0.5).sum::<glm::Vec3>() / n;
+                        (f + r * 2.0 + p * (n - 3.0)) / n
+                    }
+                    2 => p * 0.75 + (positions[sharp[0]] + positions[sharp[1]]) * 0.125,
+                    _ => p,
+                });
+            }
+
+            let mut midpoints = HashMap::with_capacity(topology.edge_faces.len());
+            for (&(a, b), adjacent) in &topology.edge_faces {
+                let point = if topology.is_sharp(&creases, (a, b)) {
+                    (positions[a] + positions[b]) * 0.5
+                } else {
+                    (positions[a] + positions[b] + face_points[adjacent[0]] + face_points[adjacent[1]]) * 0.25
+                };
+                midpoints.insert((a, b), new_positions.len());
+                new_positions.push(point);
+            }
+
+            let mut new_faces = Vec::with_capacity(faces.iter().map(|f| f.len()).sum());
+            for (f, face) in faces.iter().enumerate() {
+                let center = new_positions.len();
+                new_positions.push(face_points[f]);
+                for (j, &v) in face.iter().enumerate() {
+                    let next = face[(j + 1) % face.len()];
+                    let previous = face[(j + face.len() - 1) % face.len()];
+                    new_faces.push(vec![v, midpoints[&edge(v, next)], center, midpoints[&edge(previous, v)]]);
+                }
+            }
+
+            creases = split_creases(&creases, &midpoints);
+            positions = new_positions;
+            faces = new_faces;
+        }
+
+        PolygonMesh {
+            positions: positions.iter().flat_map(|p| [p.x, p.y, p.z]).collect(),
+            faces: faces.iter().map(|f| f.iter().map(|&v| v as u32).collect()).collect(),
+            creases: creases.iter().map(|&(a, b)| (a as u32, b as u32)).collect(),
+        }
+    }
+
+    // Fan triangulation into a renderable mesh of a single color, with smooth normals except along creases
+    pub fn triangulate(&self, color: [f32; 4]) -> Mesh {
+        let indices: Vec<u32> = self.faces.iter()
+            .flat_map(|f| (1..f.len().saturating_sub(1)).flat_map(move |j| [f[0], f[j], f[j + 1]]))
+            .collect();
+        let vertex_count = self.positions.len() / 3;
+        let mut mesh = Mesh {
+            vertices: self.positions.clone(),
+            normals: vec![],
+            colors: color.iter().cloned().cycle().take(vertex_count * 4).collect(),
+            texcoords: vec![],
+            index_count: indices.len() as i32,
+            indices,
+        };
+        let position_of: Vec<usize> = (0..vertex_count).collect();
+        let creases = self.creases.iter().map(|&(a, b)| edge(a as usize, b as usize)).collect();
+        mesh.recompute_normals(&position_of, &creases);
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_face_counts() {
+        let cube = PolygonMesh::cube(2.0).triangulate([1.0; 4]);
+        for levels in 0..3 {
+            let subdivided = cube.loop_subdivide(&[], levels);
+            assert_eq!(subdivided.indices.len() / 3, 12 * 4usize.pow(levels as u32));
+            assert_eq!(subdivided.index_count as usize, subdivided.indices.len());
+            assert_eq!(subdivided.normals.len(), subdivided.vertices.len());
+        }
+        // A vertex is added on each of the 18 edges of the triangulated cube
+        let once = cube.loop_subdivide(&[], 1);
+        assert_eq!(once.vertices.len() / 3, 8 + 18);
+    }
+
+    #[test]
+    fn loop_keeps_creased_corners() {
+        let cube = PolygonMesh::cube(2.0).triangulate([1.0; 4]);
+        let smooth = cube.loop_subdivide(&[], 2);
+        let creased = cube.loop_subdivide(&cube.sharp_edges(0.5), 2);
+        assert!(smooth.bounding_box().1.x < 1.0);
+        assert_eq!(creased.bounding_box(), (glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn catmull_clark_face_counts() {
+        let cube = PolygonMesh::cube(2.0);
+        let once = cube.catmull_clark(1);
+        // A vertex per original vertex, edge and face, and a quad per corner of every face
+        assert_eq!(once.positions.len() / 3, 8 + 12 + 6);
+        assert_eq!(once.faces.len(), 24);
+        assert!(once.faces.iter().all(|f| f.len() == 4));
+
+        let twice = cube.catmull_clark(2);
+        assert_eq!(twice.faces.len(), 96);
+        assert_eq!(twice.triangulate([1.0; 4]).indices.len() / 3, 192);
+    }
+}
@@ -0,0 +1,353 @@
+// Checks for mesh data that is unsafe or unpleasant to hand to OpenGL, and fixes what can be fixed.
+//
+// Out of range indices and attribute arrays of the wrong length make the driver read outside of the
+// buffers, which can crash it. The other issues only render wrong.
+
+use super::Mesh;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+// Normals further than this from unit length are reported
+const NORMAL_LENGTH_TOLERANCE: f32 = 1e-3;
+
+pub struct ValidationReport {
+    pub vertex_count            : usize,
+    pub triangle_count          : usize,
+    pub attribute_mismatches    : Vec<(&'static str, usize, usize)>, // attribute, expected length, actual length
+    pub index_count_mismatch    : bool,       // `index_count` does not match the length of `indices`
+    pub trailing_indices        : usize,      // indices after the last complete triangle
+    pub out_of_range_triangles  : Vec<usize>, // triangles referring to vertices that do not exist
+    pub non_finite_vertices     : Vec<usize>, // vertices with NaN or infinite positions
+    pub degenerate_triangles    : Vec<usize>, // triangles with repeated vertices or zero area
+    pub inconsistent_edges      : usize,      // edges shared by two triangles running the same way
+    pub bad_normals             : Vec<usize>, // normals which are not finite and of unit length
+}
+
+impl ValidationReport {
+    // Whether the mesh can be uploaded without the driver reading out of bounds
+    pub fn is_valid(&self) -> bool {
+        self.attribute_mismatches.is_empty()
+            && !self.index_count_mismatch
+            && self.trailing_indices == 0
+            && self.out_of_range_triangles.is_empty()
+            && self.non_finite_vertices.is_empty()
+    }
+
+    // Whether there is nothing to report at all
+    pub fn is_clean(&self) -> bool {
+        self.is_valid()
+            && self.degenerate_triangles.is_empty()
+            && self.inconsistent_edges == 0
+            && self.bad_normals.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} vertices, {} triangles", self.vertex_count, self.triangle_count)?;
+        if self.is_clean() {
+            return write!(f, ", no issues");
+        }
+        for (attribute, expected, actual) in &self.attribute_mismatches {
+            write!(f, "\n  {} has {} values, expected {}", attribute, actual, expected)?;
+        }
+        if self.index_count_mismatch {
+            write!(f, "\n  index count does not match the number of indices")?;
+        }
+        let counts = [
+            (self.trailing_indices, "trailing indices"),
+            (self.out_of_range_triangles.len(), "triangles with out of range indices"),
+            (self.non_finite_vertices.len(), "vertices with non-finite positions"),
+            (self.degenerate_triangles.len(), "degenerate triangles"),
+            (self.inconsistent_edges, "edges with inconsistent winding"),
+            (self.bad_normals.len(), "invalid or unnormalized normals"),
+        ];
+        for (count, issue) in counts.iter().filter(|(count, _)| *count > 0) {
+            write!(f, "\n  {} {}", count, issue)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct RepairSummary {
+    pub dropped_triangles : usize,
+    pub flipped_triangles : usize,
+    pub fixed_normals     : usize,
+}
+
+impl fmt::Display for RepairSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dropped {} triangles, flipped {} triangles, fixed {} normals",
+            self.dropped_triangles, self.flipped_triangles, self.fixed_normals)
+    }
+}
+
+fn is_unit_normal(n: &[f32]) -> bool {
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    length.is_finite() && (length - 1.0).abs() <= NORMAL_LENGTH_TOLERANCE
+}
+
+impl Mesh {
+    fn position(&self, v: u32) -> glm::Vec3 {
+        let v = v as usize * 3;
+        glm::vec3(self.vertices[v], self.vertices[v + 1], self.vertices[v + 2])
+    }
+
+    // Twice the area of a triangle, along its normal. The triangle must be in range.
+    fn face_normal(&self, triangle: &[u32]) -> glm::Vec3 {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|v| self.position(v));
+        glm::cross(&(b - a), &(c - a))
+    }
+
+    // Directed edges of every triangle, grouped by the undirected edge, as (triangle, runs from the lower vertex)
+    fn edge_uses(&self) -> HashMap<(u32, u32), Vec<(usize, bool)>> {
+        let mut edges: HashMap<(u32, u32), Vec<(usize, bool)>> = HashMap::new();
+        for (t, triangle) in self.indices.chunks_exact(3).enumerate() {
+            for j in 0..3 {
+                let (a, b) = (triangle[j], triangle[(j + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push((t, a < b));
+            }
+        }
+        edges
+    }
+
+    pub fn validate(&self) -> ValidationReport {
+        let vertex_count = self.vertices.len() / 3;
+        let mut report = ValidationReport {
+            vertex_count,
+            triangle_count: self.indices.len() / 3,
+            attribute_mismatches: vec![],
+            index_count_mismatch: self.index_count as usize != self.indices.len(),
+            trailing_indices: self.indices.len() % 3,
+            out_of_range_triangles: vec![],
+            non_finite_vertices: vec![],
+            degenerate_triangles: vec![],
+            inconsistent_edges: 0,
+            bad_normals: vec![],
+        };
+
+        if !self.vertices.len().is_multiple_of(3) {
+            report.attribute_mismatches.push(("vertices", vertex_count * 3, self.vertices.len()));
+        }
+        if self.normals.len() != vertex_count * 3 {
+            report.attribute_mismatches.push(("normals", vertex_count * 3, self.normals.len()));
+        }
+        if self.colors.len() != vertex_count * 4 {
+            report.attribute_mismatches.push(("colors", vertex_count * 4, self.colors.len()));
+        }
+        if !self.texcoords.is_empty() && self.texcoords.len() != vertex_count * 2 {
+            report.attribute_mismatches.push(("texcoords", vertex_count * 2, self.texcoords.len()));
+        }
+
+        report.non_finite_vertices = (0..vertex_count)
+            .filter(|&v| self.vertices[v * 3..v * 3 + 3].iter().any(|c| !c.is_finite()))
+            .collect();
+        if self.normals.len() == vertex_count * 3 {
+            report.bad_normals = (0..vertex_count).filter(|&v| !is_unit_normal(&self.normals[v * 3..v * 3 + 3])).collect();
+        }
+
+        for (t, triangle) in self.indices.chunks_exact(3).enumerate() {
+            if triangle.iter().any(|&v| v as usize >= vertex_count) {
+                report.out_of_range_triangles.push(t);
+            } else if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[2] == triangle[0]
+                || glm::length2(&self.face_normal(triangle)) == 0.0 {
+                report.degenerate_triangles.push(t);
+            }
+        }
+
+        // Two triangles on either side of an edge must run along it in opposite directions
+        if report.out_of_range_triangles.is_empty() {
+            report.inconsistent_edges = self.edge_uses().values()
+                .filter(|uses| uses.len() == 2 && uses[0].1 == uses[1].1)
+                .count();
+        }
+
+        report
+    }
+
+    // Drops triangles which are out of range, degenerate or touch non-finite vertices, makes the
+    // winding consistent across shared edges, and replaces missing or broken normals. Missing colors
+    // become white, and texture coordinates of the wrong length are dropped.
+    pub fn repair(&mut self) -> RepairSummary {
+        let vertex_count = self.vertices.len() / 3;
+        self.vertices.truncate(vertex_count * 3);
+        let triangle_count = self.indices.len() / 3;
+
+        let finite: Vec<bool> = self.vertices.chunks_exact(3).map(|p| p.iter().all(|c| c.is_finite())).collect();
+        let indices = std::mem::take(&mut self.indices);
+        for triangle in indices.chunks_exact(3) {
+            let in_range = triangle.iter().all(|&v| (v as usize) < vertex_count && finite[v as usize]);
+            let distinct = triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[2] != triangle[0];
+            if in_range && distinct && glm::length2(&self.face_normal(triangle)) > 0.0 {
+                self.indices.extend_from_slice(triangle);
+            }
+        }
+        self.index_count = self.indices.len() as i32;
+
+        // No triangle refers to these any more, but they still have to be uploaded
+        for c in self.vertices.iter_mut().filter(|c| !c.is_finite()) {
+            *c = 0.0;
+        }
+
+        if self.colors.len() != vertex_count * 4 {
+            self.colors.resize(vertex_count * 4, 1.0);
+        }
+        if !self.texcoords.is_empty() && self.texcoords.len() != vertex_count * 2 {
+            self.texcoords.clear();
+        }
+
+        let flipped_triangles = self.fix_winding();
+        let fixed_normals = self.fix_normals();
+
+        RepairSummary {
+            dropped_triangles: triangle_count - self.indices.len() / 3,
+            flipped_triangles,
+            fixed_normals,
+        }
+    }
+
+    // Orients every connected patch of triangles consistently, by flood fill across the edges shared
+    // by exactly two triangles. Each patch is then turned to agree with most of its vertex normals.
+    fn fix_winding(&mut self) -> usize {
+        let triangle_count = self.indices.len() / 3;
+        let edges = self.edge_uses();
+        let mut neighbours: Vec<Vec<(usize, bool)>> = vec![vec![]; triangle_count];
+        for uses in edges.values().filter(|uses| uses.len() == 2) {
+            let [(a, a_up), (b, b_up)] = [uses[0], uses[1]];
+            // Flipping one of the two is needed when they run the same way along the edge
+            neighbours[a].push((b, a_up == b_up));
+            neighbours[b].push((a, a_up == b_up));
+        }
+
+        let has_normals = self.normals.len() == self.vertices.len();
+        let mut flip: Vec<Option<bool>> = vec![None; triangle_count];
+        let mut flipped = 0;
+        for seed in 0..triangle_count {
+            if flip[seed].is_some() {
+                continue;
+            }
+            flip[seed] = Some(false);
+            let mut patch = vec![];
+            let mut queue = VecDeque::from(vec![seed]);
+            while let Some(t) = queue.pop_front() {
+                patch.push(t);
+                for &(other, needs_flip) in &neighbours[t] {
+                    if flip[other].is_none() {
+                        flip[other] = Some(flip[t].unwrap() != needs_flip);
+                        queue.push_back(other);
+                    }
+                }
+            }
+
+            // Turn the whole patch over if most of it faces away from the normals it was given
+            let mut agreement = 0.0;
+            if has_normals {
+                for &t in &patch {
+                    let triangle = &self.indices[t * 3..t * 3 + 3];
+                    let face = self.face_normal(triangle);
+                    let sign = if flip[t].unwrap() { -1.0 } else { 1.0 };
+                    for &v in triangle {
+                        let n = glm::vec3(self.normals[v as usize * 3], self.normals[v as usize * 3 + 1], self.normals[v as usize * 3 + 2]);
+                        if n.iter().all(|c| c.is_finite()) {
+                            agreement += sign * glm::dot(&face, &n);
+                        }
+                    }
+                }
+            }
+            for &t in &patch {
+                let turn = flip[t].unwrap() != (agreement < 0.0);
+                if turn {
+                    self.indices.swap(t * 3 + 1, t * 3 + 2);
+                    flipped += 1;
+                }
+            }
+        }
+        flipped
+    }
+
+    // Normalizes the normals, and replaces those which are missing, zero or not finite with the
+    // area weighted average of the surrounding faces
+    fn fix_normals(&mut self) -> usize {
+        let vertex_count = self.vertices.len() / 3;
+        let mut face_normals = vec![glm::Vec3::zeros(); vertex_count];
+        for triangle in self.indices.chunks_exact(3) {
+            let n = self.face_normal(triangle);
+            for &v in triangle {
+                face_normals[v as usize] += n;
+            }
+        }
+
+        let mut fixed = 0;
+        let missing = self.normals.len() != vertex_count * 3;
+        if missing {
+            self.normals = vec![0.0; vertex_count * 3];
+        }
+        for (n, face) in self.normals.chunks_exact_mut(3).zip(face_normals) {
+            if is_unit_normal(n) {
+                continue;
+            }
+            let given = glm::vec3(n[0], n[1], n[2]);
+            let length = glm::length(&given);
+            let replacement = if length.is_finite() && length > 0.0 {
+                given / length
+            } else if glm::length2(&face) > 0.0 {
+                glm::normalize(&face)
+            } else {
+                glm::vec3(0.0, 1.0, 0.0) // unused by any triangle
+            };
+            n.copy_from_slice(replacement.as_slice());
+            fixed += 1;
+        }
+        fixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PolygonMesh;
+    use super::*;
+
+    fn cube() -> Mesh {
+        PolygonMesh::cube(2.0).triangulate([1.0; 4])
+    }
+
+    #[test]
+    fn cube_is_clean() {
+        let report = cube().validate();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!((report.vertex_count, report.triangle_count), (8, 12));
+    }
+
+    #[test]
+    fn repair_removes_degenerate_triangles() {
+        let mut mesh = cube();
+        // Vertex 8, halfway between vertices 0 and 1
+        let midpoint: Vec<f32> = (0..3).map(|i| (mesh.vertices[i] + mesh.vertices[3 + i]) / 2.0).collect();
+        mesh.vertices.extend_from_slice(&midpoint);
+        mesh.normals.extend_from_slice(&[0.0, 1.0, 0.0]);
+        mesh.colors.extend_from_slice(&[1.0; 4]);
+        // Vertex 0 twice, vertex 1 twice, three distinct vertices along one line, and a vertex that does not exist
+        mesh.indices.extend_from_slice(&[0, 0, 1, 0, 1, 1, 0, 8, 1, 0, 1, 9]);
+        mesh.index_count = mesh.indices.len() as i32;
+        let report = mesh.validate();
+        assert!(!report.is_valid());
+        assert_eq!(report.degenerate_triangles, vec![12, 13, 14]);
+        assert_eq!(report.out_of_range_triangles, vec![15]);
+
+        let summary = mesh.repair();
+        assert_eq!(summary.dropped_triangles, 4);
+        assert_eq!(mesh.indices, cube().indices);
+        assert!(mesh.validate().is_clean());
+    }
+
+    #[test]
+    fn fix_winding_flips_an_inverted_face() {
+        let mut mesh = cube();
+        mesh.indices.swap(4, 5);
+        assert_eq!(mesh.validate().inconsistent_edges, 3);
+
+        assert_eq!(mesh.fix_winding(), 1);
+        assert_eq!(mesh.indices, cube().indices);
+        assert!(mesh.validate().is_clean());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::shader::{self, Shader, ShaderBuilder, ShaderError};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use std::{fmt, ops};
+
+// Variants of one shader program for materials that need slightly different shading.
+//
+// Each feature of a material is a bit of `Features`, and each variant is the program built with
+// `#define NAME 1` for every feature it has, so the shaders tell the variants apart with #ifdef.
+// Variants are built the first time they are asked for and kept from then on. One that fails to build
+// is tried again once any of its files changes, see `retry_failed`.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE           : Features = Features(0);
+    pub const TEXTURED       : Features = Features(1 << 0); // Colored by the albedo texture rather than by vertex colors
+    pub const UNLIT          : Features = Features(1 << 1); // Drawn in full color, regardless of the lights
+    pub const PACKED_NORMALS : Features = Features(1 << 2); // Normals octahedral encoded, see `VertexFormat::features`
+
+    // Each feature with the name it is defined as in the shaders
+    const DEFINES: [(Features, &'static str); 3] = [
+        (Features::TEXTURED,       "TEXTURED"),
+        (Features::UNLIT,          "UNLIT"),
+        (Features::PACKED_NORMALS, "PACKED_NORMALS"),
+    ];
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // The names defined for the features of the variant
+    pub fn defines(self) -> impl Iterator<Item = &'static str> {
+        Features::DEFINES.iter().filter(move |(feature, _)| self.contains(*feature)).map(|(_, name)| *name)
+    }
+}
+
+impl ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.defines().collect();
+        if names.is_empty() {
+            f.write_str("default")
+        } else {
+            f.write_str(&names.join(" | "))
+        }
+    }
+}
+
+enum Variant {
+    Built(Box<Shader>),
+    Failed(Vec<(String, Option<SystemTime>)>), // The files it was built from, and when they were modified then
+}
+
+pub struct ShaderPermutations {
+    stages          : Vec<String>,
+    defines         : Vec<(String, String)>,     // Given to every variant, before those of its features
+    cache_directory : Option<String>,            // Of program binaries
+    variants        : HashMap<Features, Variant>,
+}
+
+impl ShaderPermutations {
+    // Variants of the program with the given stages. Nothing is built until a variant is asked for.
+    pub fn new(paths: &[&str], defines: &[(&str, &str)]) -> ShaderPermutations {
+        ShaderPermutations {
+            stages          : paths.iter().map(|path| path.to_string()).collect(),
+            defines         : defines.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            cache_directory : None,
+            variants        : HashMap::new(),
+        }
+    }
+
+    // Caches the program binary of each variant in `directory`, see `ShaderBuilder::binary_cache`
+    pub fn binary_cache(mut self, directory: &str) -> ShaderPermutations {
+        self.cache_directory = Some(directory.to_string());
+        self
+    }
+
+    // The variant with `features`, built on first use. A variant that fails to build says why, and is
+    // None until it builds after a change to its files.
+    pub unsafe fn program(&mut self, features: Features) -> Option<&Shader> {
+        if !self.variants.contains_key(&features) {
+            let variant = self.build(features);
+            self.variants.insert(features, variant);
+        }
+        match &self.variants[&features] {
+            Variant::Built(shader) => Some(shader),
+            Variant::Failed(_) => None,
+        }
+    }
+
+    // Builds again the variants that failed to build and whose files changed since. Returns those that
+    // build now.
+    pub unsafe fn retry_failed(&mut self) -> Vec<Features> {
+        let changed: Vec<Features> = self.variants.iter().filter_map(|(&features, variant)| match variant {
+            Variant::Failed(files) if files.iter().any(|(path, modified)| shader::modified(path) != *modified) => Some(features),
+            _ => None,
+        }).collect();
+
+        let mut built = vec![];
+        for features in changed {
+            let variant = self.build(features);
+            if let Variant::Built(_) = variant {
+                built.push(features);
+            }
+            self.variants.insert(features, variant);
+        }
+        built
+    }
+
+    // Builds a variant, saying why when it fails
+    unsafe fn build(&self, features: Features) -> Variant {
+        let mut defines = self.defines.clone();
+        defines.extend(features.defines().map(|name| (name.to_string(), "1".to_string())));
+        match self.link(&defines) {
+            Ok(shader) => Variant::Built(Box::new(shader)),
+            Err(e) => {
+                println!("{} ({}): {}", self.stages.join(", "), features, e);
+                Variant::Failed(self.files(&defines))
+            }
+        }
+    }
+
+    unsafe fn link(&self, defines: &[(String, String)]) -> Result<Shader, ShaderError> {
+        let mut builder = ShaderBuilder::new();
+        if let Some(directory) = &self.cache_directory {
+            builder = builder.binary_cache(directory);
+        }
+        for (name, value) in defines {
+            builder = builder.define(name, value);
+        }
+        for path in &self.stages {
+            builder = builder.attach_file(path)?;
+        }
+        builder.link()
+    }
+
+    // The stages and the files they include, as far as they can be read, with when they were modified
+    fn files(&self, defines: &[(String, String)]) -> Vec<(String, Option<SystemTime>)> {
+        let mut files = self.stages.clone();
+        for path in &self.stages {
+            if let Ok((_, included)) = shader::preprocess(path, defines) {
+                files.extend(included.into_iter().filter(|file| file != path));
+            }
+        }
+        files.sort();
+        files.dedup();
+        files.into_iter().map(|path| (path.clone(), shader::modified(&path))).collect()
+    }
+
+    // The variants built so far, to reload when their files change
+    pub fn variants_mut(&mut self) -> impl Iterator<Item = (Features, &mut Shader)> {
+        self.variants.iter_mut().filter_map(|(&features, variant)| match variant {
+            Variant::Built(shader) => Some((features, &mut **shader)),
+            Variant::Failed(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_features_define_nothing() {
+        assert_eq!(Features::NONE.defines().count(), 0);
+        assert_eq!(Features::NONE.to_string(), "default");
+        assert_eq!(Features::default(), Features::NONE);
+    }
+
+    #[test]
+    fn each_feature_is_named_once() {
+        let all = Features::TEXTURED | Features::UNLIT | Features::PACKED_NORMALS;
+        assert_eq!(all.defines().collect::<Vec<_>>(), ["TEXTURED", "UNLIT", "PACKED_NORMALS"]);
+        assert_eq!(all.to_string(), "TEXTURED | UNLIT | PACKED_NORMALS");
+
+        let twice = Features::UNLIT | Features::TEXTURED | Features::UNLIT;
+        assert_eq!(twice.defines().collect::<Vec<_>>(), ["TEXTURED", "UNLIT"]);
+        assert_eq!(twice.to_string(), "TEXTURED | UNLIT");
+        for (feature, name) in Features::DEFINES {
+            assert_eq!(feature.defines().collect::<Vec<_>>(), [name]);
+            assert_eq!(feature.to_string(), name);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gpu_mesh::GpuMesh;
+use crate::mesh::{Bvh, Mesh, ObjExport, Ray};
+use crate::permutations::Features;
+use std::mem::ManuallyDrop;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+
+// Used to create an unholy abomination upon which you should not cast your gaze. This ended up
+// being a necessity due to wanting to keep the code written by students as "straight forward" as
+// possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
+// But it works, and you're more than welcome to pretend it doesn't exist! In case you're curious
+// about how it works: It allocates memory on the heap (Box), promises to prevent it from being
+// moved or deallocated until dropped (Pin) and finally prevents the compiler from dropping it
+// automatically at all (ManuallyDrop).
+// ...
+// If that sounds like a janky solution, it's because it is!
+// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of
+// having what I arbitrarily decided to be the required level of "simplicity of use".
+//
+// LIMITATION: nodes are never dropped, so neither is anything they hold. A GpuMesh given to a node,
+// also as a level of detail, keeps its VAO and buffers until the program exits. Only meshes that
+// never went into a node are freed by their Drop.
+pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+
+// A coarser version of what a node draws, used once the node covers less than `screen_size` of the
+// height of the screen
+pub struct LevelOfDetail {
+    pub gpu_mesh    : Rc<GpuMesh>,
+    pub screen_size : f32,
+}
+
+// What a ray hit in the scene, with everything in world coordinates
+pub struct Hit<'a> {
+    pub node     : &'a SceneNode,
+    pub triangle : usize,     // Which triangle of the node's mesh
+    pub point    : glm::Vec3,
+    pub normal   : glm::Vec3, // Unit length, on the side given by the winding of the triangle
+    pub distance : f32,       // How far along the ray, in multiples of its direction
+}
+
+pub struct SceneNode {
+    pub name            : String,      // What to call me when exported, may be empty
+    pub position        : glm::Vec3,   // Where I should be in relation to my parent
+    pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
+    pub scale           : glm::Vec3,   // How I should be scaled
+    pub reference_point : glm::Vec3,   // The point I shall rotate and scale about
+
+    pub gpu_mesh    : Option<Rc<GpuMesh>>, // What I should draw, None if I only hold other nodes
+    pub draw_mode   : u32,             // Which primitives to draw it as, gl::TRIANGLES or gl::PATCHES
+    pub program_id  : u32,             // Which shader program to draw it with, 0 for the variant of `features`
+    pub features    : Features,        // What my material needs of the shader, to pick its variant
+
+    pub lods            : Vec<LevelOfDetail>, // Cheaper versions of what I draw, from finest to coarsest
+    pub bounding_sphere : glm::Vec4,          // Where what I draw is, center and radius in my own coordinates
+    pub bvh             : Option<Rc<Bvh>>,    // The triangles of what I draw, to cast rays against, None to be ignored by rays
+    pub highlighted     : bool,               // Whether to draw me brightened, as when picked with the mouse
+    pub mesh            : Option<Rc<Mesh>>,   // What I draw, kept on the CPU side for exporting, None to be left out
+
+    pub children: Vec<*mut SceneNode>, // Those I command
+}
+
+impl SceneNode {
+
+    pub fn new() -> Node {
+        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name            : String::new(),
+            position        : glm::zero(),
+            rotation        : glm::zero(),
+            scale           : glm::vec3(1.0, 1.0, 1.0),
+            reference_point : glm::zero(),
+            gpu_mesh        : None,
+            draw_mode       : gl::TRIANGLES,
+            program_id      : 0,
+            features        : Features::NONE,
+            lods            : vec![],
+            bounding_sphere : glm::zero(),
+            bvh             : None,
+            highlighted     : false,
+            mesh            : None,
+            children        : vec![],
+        })))
+    }
+
+    pub fn from_mesh(gpu_mesh: Rc<GpuMesh>) -> Node {
+        let mut node = SceneNode::new();
+        node.gpu_mesh = Some(gpu_mesh);
+        node
+    }
+
+    pub fn add_child(&mut self, child: &SceneNode) {
+        self.children.push(child as *const SceneNode as *mut SceneNode)
+    }
+
+    // Levels must be added from finest to coarsest, that is with decreasing screen sizes
+    pub fn add_lod(&mut self, gpu_mesh: Rc<GpuMesh>, screen_size: f32) {
+        self.lods.push(LevelOfDetail { gpu_mesh, screen_size })
+    }
+
+    // What to draw when covering `screen_size` of the height of the screen
+    pub fn select_lod(&self, screen_size: f32) -> Option<&GpuMesh> {
+        self.lods.iter()
+            .rev()
+            .find(|lod| screen_size < lod.screen_size)
+            .map(|lod| &*lod.gpu_mesh)
+            .or(self.gpu_mesh.as_deref())
+    }
+
+    // My transformation relative to my parent: scaled and rotated about the reference point, then moved
+    pub fn local_transform(&self) -> glm::Mat4 {
+        glm::translation(&(self.position + self.reference_point))
+            * glm::rotation(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0))
+            * glm::rotation(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::rotation(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
+            * glm::scaling(&self.scale)
+            * glm::translation(&-self.reference_point)
+    }
+
+    // The closest hit of a ray, given in world coordinates, against me and my descendants.
+    // `transformation_so_far` is the transformation of my parent, the identity for the root.
+    pub fn raycast(&self, ray: &Ray, transformation_so_far: &glm::Mat4) -> Option<Hit<'_>> {
+        let mut closest = None;
+        self.raycast_closest(ray, transformation_so_far, &mut closest);
+        closest
+    }
+
+    fn raycast_closest<'a>(&'a self, ray: &Ray, transformation_so_far: &glm::Mat4, closest: &mut Option<Hit<'a>>) {
+        let transformation = transformation_so_far * self.local_transform();
+        if let Some(bvh) = &self.bvh {
+            // Distances along the ray are kept by transforming it, so hits in different nodes compare directly
+            let max_distance = closest.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+            if let Some(inverse) = transformation.try_inverse() {
+                if let Some(hit) = bvh.raycast(&ray.transform(&inverse), max_distance) {
+                    let normal = glm::mat4_to_mat3(&inverse).transpose() * hit.normal;
+                    *closest = Some(Hit {
+                        node     : self,
+                        triangle : hit.triangle,
+                        point    : ray.at(hit.distance),
+                        normal   : glm::normalize(&normal),
+                        distance : hit.distance,
+                    });
+                }
+            }
+        }
+        for &child in &self.children {
+            unsafe { (*child).raycast_closest(ray, &transformation, closest) };
+        }
+    }
+
+    // Adds the meshes of me and my descendants that are drawn to an export, transformed to world coordinates.
+    // `transformation_so_far` is the transformation of my parent, the identity for the root.
+    pub fn export(&self, export: &mut ObjExport, transformation_so_far: &glm::Mat4) -> io::Result<()> {
+        let transformation = transformation_so_far * self.local_transform();
+        if let Some(mesh) = self.mesh.as_ref().filter(|_| self.gpu_mesh.is_some()) {
+            let name = if self.name.is_empty() { "node" } else { &self.name };
+            export.add(name, mesh, &transformation)?;
+        }
+        for &child in &self.children {
+            unsafe { (*child).export(export, &transformation)? };
+        }
+        Ok(())
+    }
+
+    pub fn get_child(& mut self, index: usize) -> & mut SceneNode {
+        unsafe {
+            &mut (*self.children[index])
+        }
+    }
+
+    pub fn get_n_children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn print(&self) {
+        println!(
+"SceneNode {{
+    VAO:       {}
+    Indices:   {}
+    Children:  {}
+    Position:  [{:.2}, {:.2}, {:.2}]
+    Rotation:  [{:.2}, {:.2}, {:.2}]
+    Reference: [{:.2}, {:.2}, {:.2}]
+}}",
+            self.gpu_mesh.as_ref().map_or(0, |m| m.vao_id()),
+            self.gpu_mesh.as_ref().map_or(0, |m| m.index_count()),
+            self.children.len(),
+            self.position.x,
+            self.position.y,
+            self.position.z,
+            self.rotation.x,
+            self.rotation.y,
+            self.rotation.z,
+            self.reference_point.x,
+            self.reference_point.y,
+            self.reference_point.z,
+        );
+    }
+
+}
+
+
+// You can also use square brackets to access the children of a SceneNode
+use std::ops::{Index, IndexMut};
+impl Index<usize> for SceneNode {
+    type Output = SceneNode;
+    fn index(&self, index: usize) -> &SceneNode {
+        unsafe {
+            & *(self.children[index] as *const SceneNode)
+        }
+    }
+}
+impl IndexMut<usize> for SceneNode {
+    fn index_mut(&mut self, index: usize) -> &mut SceneNode {
+        unsafe {
+            &mut (*self.children[index])
+        }
+    }
+}
@@ -0,0 +1,699 @@
+use gl;
+use std::{
+    ptr,
+    str,
+    fmt,
+    io,
+    error::Error,
+    ffi::CString,
+    path::{Path, PathBuf},
+    time::SystemTime,
+    cell::RefCell,
+    collections::HashSet,
+};
+
+mod binary_cache;
+mod preprocess;
+mod reflect;
+
+pub use reflect::{ActiveAttribute, ActiveStorageBlock, ActiveUniform, ActiveUniformBlock, BlockMember};
+
+pub struct Shader {
+    pub program_id: u32,
+    stages          : Vec<String>,              // The file of each stage, to build it again when any file it read changes
+    defines         : Vec<(String, String)>,
+    files           : Vec<WatchedFile>,         // Every file read, included ones too. Empty when built from strings.
+    reflection      : reflect::Reflection,      // Asked of the driver once, when linked
+    reported        : RefCell<HashSet<String>>, // Uniforms already misused in debug builds, to say so only once
+    cache_directory : Option<PathBuf>,          // Of program binaries, used again when reloading
+}
+
+struct WatchedFile {
+    path     : String,
+    modified : Option<SystemTime>, // When it was last read, None when that could not be told
+}
+
+pub(crate) fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The files modified or gone since they were last read or checked. Their times are brought up to date,
+// so each change is found once.
+fn changed_files(files: &mut [WatchedFile]) -> Vec<String> {
+    let mut changed = vec![];
+    for file in files.iter_mut() {
+        let now = modified(&file.path);
+        if now != file.modified {
+            file.modified = now;
+            changed.push(file.path.clone());
+        }
+    }
+    changed
+}
+
+// The source of a stage as the driver is given it, with its includes pasted in and `#define name value`
+// for each of `defines` after its #version line, and the file of each of its source strings. Needs no
+// OpenGL context, for checking shaders offline.
+pub fn preprocess(path: &str, defines: &[(String, String)]) -> Result<(String, Vec<String>), ShaderError> {
+    let preprocessed = preprocess::preprocess(path, defines)?;
+    Ok((preprocessed.source, preprocessed.files.into_iter().map(|file| file.path).collect()))
+}
+
+pub struct ShaderBuilder {
+    program_id: u32,
+    sources: Vec<Source>,           // Compiled when linking, unless the program binary is cached
+    defines: Vec<(String, String)>, // Given to the files attached from then on
+    stages: Vec<String>,
+    files: Vec<WatchedFile>,
+    cache_directory: Option<PathBuf>,
+}
+
+// The source of one stage, ready to compile
+struct Source {
+    text         : String,
+    shader_type  : ShaderType,
+    file         : String,      // The file of the stage, or "<source>"
+    source_files : Vec<String>, // The file of each source string in `text`, to locate errors in
+}
+
+impl Source {
+    // What tells the stage apart in the name of a cached program binary. Sources given as strings
+    // have no file of their own, so they go by a hash of the source.
+    fn cache_name(&self) -> String {
+        match self.file.as_str() {
+            "<source>" => format!("<source {:016x}>", crate::util::checksum(self.text.as_bytes())),
+            file => file.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShaderType {
+    Vertex,
+    Fragment,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+    Compute,   // Alone in a program of its own, run by `dispatch`
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: String, error: io::Error },
+    UnknownExtension(String),                                  // The path of a file that is none of .vert, .frag, ...
+    Include { file: String, line: usize, message: String },    // A malformed #include
+    Compile { stage: ShaderType, file: String, log: String },  // The whole info log, its lines located as file:line
+    Link { log: String },
+}
+
+impl Shader {
+    // Compiles and links a program from the files of each of its stages, told apart by their extensions
+    pub unsafe fn from_files(paths: &[&str]) -> Result<Shader, ShaderError> {
+        Shader::from_files_with_defines(paths, &[])
+    }
+
+    // The same, with `#define name value` for each of `defines` at the top of every stage
+    pub unsafe fn from_files_with_defines(paths: &[&str], defines: &[(&str, &str)]) -> Result<Shader, ShaderError> {
+        Shader::build(ShaderBuilder::new(), paths, defines)
+    }
+
+    // The same, loading the program binary from `cache_directory` when it is current there and
+    // storing it there otherwise
+    pub unsafe fn from_files_cached(paths: &[&str], defines: &[(&str, &str)], cache_directory: &str) -> Result<Shader, ShaderError> {
+        Shader::build(ShaderBuilder::new().binary_cache(cache_directory), paths, defines)
+    }
+
+    unsafe fn build(mut builder: ShaderBuilder, paths: &[&str], defines: &[(&str, &str)]) -> Result<Shader, ShaderError> {
+        for (name, value) in defines {
+            builder = builder.define(name, value);
+        }
+        for path in paths {
+            builder = builder.attach_file(path)?;
+        }
+        builder.link()
+    }
+
+    // Rebuilds the program when any of its files changed since it was built, and swaps in the new
+    // program only once it compiles and links. On failure the old program is kept and the error
+    // returned, and the files are not tried again until they change once more. Returns whether the
+    // program was replaced, after which it must be activated again and its plain uniforms set again.
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, ShaderError> {
+        if changed_files(&mut self.files).is_empty() {
+            return Ok(false);
+        }
+
+        let mut builder = ShaderBuilder::new();
+        if let Some(directory) = &self.cache_directory {
+            builder = builder.binary_cache(directory);
+        }
+        for (name, value) in &self.defines {
+            builder = builder.define(name, value);
+        }
+        for path in &self.stages {
+            builder = builder.attach_file(path)?;
+        }
+        let shader = builder.link()?;
+        gl::DeleteProgram(self.program_id);
+        self.program_id = shader.program_id;
+        self.files = shader.files; // Includes may have come or gone
+        self.reflection = shader.reflection;
+        self.reported.borrow_mut().clear();
+        Ok(true)
+    }
+
+    // The location of a uniform outside of any block, -1 when the program has none of that name
+    pub fn get_uniform_location(&self, name: &str) -> i32 {
+        self.reflection.uniforms.get(name).map_or(-1, |uniform| uniform.location)
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&ActiveUniform> {
+        self.reflection.uniforms.get(name)
+    }
+
+    pub unsafe fn activate(&self) {
+        gl::UseProgram(self.program_id);
+    }
+
+    pub fn active_attributes(&self) -> &[ActiveAttribute] {
+        &self.reflection.attributes
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&ActiveUniformBlock> {
+        self.reflection.blocks.get(name)
+    }
+
+    pub fn storage_block(&self, name: &str) -> Option<&ActiveStorageBlock> {
+        self.reflection.storage_blocks.get(name)
+    }
+
+    // The local size of a compute program, None for any other
+    pub fn work_group_size(&self) -> Option<[u32; 3]> {
+        self.reflection.work_group_size
+    }
+
+    // Runs a compute program over `groups` work groups along x, y and z. Its writes to memory are seen
+    // by later commands only after a `compute::memory_barrier` for the way they read it.
+    pub unsafe fn dispatch(&self, groups: [u32; 3]) {
+        assert!(self.work_group_size().is_some(), "dispatch of a program without a compute stage");
+        gl::UseProgram(self.program_id);
+        gl::DispatchCompute(groups[0], groups[1], groups[2]);
+    }
+
+    // Runs a compute program over at least `items` invocations along x, y and z, rounded up to
+    // whole work groups. The shader must skip the invocations past the end.
+    pub unsafe fn dispatch_items(&self, items: [u32; 3]) {
+        let size = self.work_group_size().expect("dispatch of a program without a compute stage");
+        self.dispatch([0, 1, 2].map(|i| items[i].div_ceil(size[i])));
+    }
+
+    // The typed setters below set the uniform of the program whether it is active or not. In debug
+    // builds they say so when the program has no active uniform of the name, or one of another type.
+    pub unsafe fn set_mat4(&self, name: &str, value: &glm::Mat4) {
+        if let Some(location) = self.checked_location(name, &[gl::FLOAT_MAT4], "mat4") {
+            gl::ProgramUniformMatrix4fv(self.program_id, location, 1, gl::FALSE, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec3(&self, name: &str, value: &glm::Vec3) {
+        if let Some(location) = self.checked_location(name, &[gl::FLOAT_VEC3], "vec3") {
+            gl::ProgramUniform3fv(self.program_id, location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec4(&self, name: &str, value: &glm::Vec4) {
+        if let Some(location) = self.checked_location(name, &[gl::FLOAT_VEC4], "vec4") {
+            gl::ProgramUniform4fv(self.program_id, location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_f32(&self, name: &str, value: f32) {
+        if let Some(location) = self.checked_location(name, &[gl::FLOAT], "float") {
+            gl::ProgramUniform1f(self.program_id, location, value);
+        }
+    }
+
+    // Binds the texture to the texture unit and points the sampler `name` at that unit
+    pub unsafe fn set_texture(&self, name: &str, unit: u32, texture_id: u32) {
+        let target = self.uniform(name).and_then(|uniform| reflect::sampler_target(uniform.gl_type));
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(target.unwrap_or(gl::TEXTURE_2D), texture_id);
+        if target.is_none() {
+            self.report(name, "sampler");
+        } else if let Some(uniform) = self.uniform(name) {
+            gl::ProgramUniform1i(self.program_id, uniform.location, unit as i32);
+        }
+    }
+
+    // The location of the uniform when it is one of the `expected` types, reporting it otherwise
+    fn checked_location(&self, name: &str, expected: &[u32], wanted: &str) -> Option<i32> {
+        match self.uniform(name) {
+            Some(uniform) if expected.contains(&uniform.gl_type) => Some(uniform.location),
+            _ => {
+                self.report(name, wanted);
+                None
+            }
+        }
+    }
+
+    // Says once per name, in debug builds, that the uniform is missing or not of the type wanted
+    fn report(&self, name: &str, wanted: &str) {
+        if let Some(message) = self.misuse(name, wanted) {
+            eprintln!("{}", message);
+        }
+    }
+
+    // What `report` says, None when it has said so for the name already or in release builds
+    fn misuse(&self, name: &str, wanted: &str) -> Option<String> {
+        if !cfg!(debug_assertions) || !self.reported.borrow_mut().insert(name.to_string()) {
+            return None;
+        }
+        let program = if self.stages.is_empty() { format!("program {}", self.program_id) } else { self.stages.join(", ") };
+        Some(match self.uniform(name) {
+            Some(uniform) => format!("{}: uniform {} is declared {} but set as {}", program, name, reflect::type_name(uniform.gl_type), wanted),
+            None => format!("{}: no active uniform {} to set as {}", program, name, wanted),
+        })
+    }
+}
+
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> Self {
+        match shader_type {
+            ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
+            ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
+            ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
+            ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
+            ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
+        }
+    }
+}
+
+impl ShaderType {
+    fn from_ext(ext: &std::ffi::OsStr) -> Option<ShaderType> {
+        match ext.to_str()? {
+            "vert" => { Some(ShaderType::Vertex) },
+            "frag" => { Some(ShaderType::Fragment) },
+            "tcs"  => { Some(ShaderType::TessellationControl) },
+            "tes"  => { Some(ShaderType::TessellationEvaluation) },
+            "geom" => { Some(ShaderType::Geometry) },
+            "comp" => { Some(ShaderType::Compute) },
+            _ => { None },
+        }
+    }
+}
+
+impl fmt::Display for ShaderType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ShaderType::Vertex                 => "vertex",
+            ShaderType::Fragment               => "fragment",
+            ShaderType::TessellationControl    => "tessellation control",
+            ShaderType::TessellationEvaluation => "tessellation evaluation",
+            ShaderType::Geometry               => "geometry",
+            ShaderType::Compute                => "compute",
+        })
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Io { path, error } =>
+                write!(f, "could not read shader {}: {}", path, error),
+            ShaderError::UnknownExtension(path) =>
+                write!(f, "could not tell the stage of shader {} from its extension", path),
+            ShaderError::Include { file, line, message } =>
+                write!(f, "{}:{}: {}", file, line, message),
+            ShaderError::Compile { stage, file, log } =>
+                write!(f, "{} shader {} failed to compile:\n{}", stage, file, log),
+            ShaderError::Link { log } =>
+                write!(f, "shader program failed to link:\n{}", log),
+        }
+    }
+}
+
+impl Error for ShaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShaderError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Compiler messages start with the number of the source string and the line in it, in one of a few
+// forms depending on the driver:
+//   Mesa           0:12(5): error: ...
+//   NVIDIA         0(12) : error C0000: ...
+//   AMD and Intel  ERROR: 0:12: ...
+// Returns the number of the source string, the line, and the byte range they take up.
+fn log_location(line: &str) -> Option<(usize, usize, std::ops::Range<usize>)> {
+    let start = ["ERROR: ", "WARNING: "].iter()
+        .find(|prefix| line.starts_with(*prefix))
+        .map_or(0, |prefix| prefix.len());
+    let digits = |from: usize| line[from..].find(|c: char| !c.is_ascii_digit()).map_or(line.len(), |n| from + n);
+
+    let source_end = digits(start);
+    let source = line[start..source_end].parse().ok()?;
+    let (line_start, closing) = match line[source_end..].chars().next()? {
+        ':' => (source_end + 1, None),
+        '(' => (source_end + 1, Some(')')),
+        _ => return None,
+    };
+    let line_end = digits(line_start);
+    let line_number = line[line_start..line_end].parse().ok()?;
+    let end = match closing {
+        Some(c) if line[line_end..].starts_with(c) => line_end + 1,
+        Some(_) => return None,
+        None => line_end,
+    };
+    Some((source, line_number, start..end))
+}
+
+// Rewrites the locations of an info log as file:line, `files` naming each source string
+fn map_log(log: &str, files: &[&str]) -> String {
+    log.lines().map(|line| match log_location(line) {
+        Some((source, line_number, range)) if source < files.len() =>
+            format!("{}{}:{}{}", &line[..range.start], files[source], line_number, &line[range.end..]),
+        _ => line.to_string(),
+    }).collect::<Vec<_>>().join("\n")
+}
+
+unsafe fn shader_info_log(shader_id: u32) -> String {
+    let mut length = 0;
+    gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetShaderInfoLog(shader_id, length.max(1), &mut written, info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    info_log.truncate(written as usize);
+    String::from_utf8_lossy(&info_log).trim_end().to_string()
+}
+
+unsafe fn program_info_log(program_id: u32) -> String {
+    let mut length = 0;
+    gl::GetProgramiv(program_id, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetProgramInfoLog(program_id, length.max(1), &mut written, info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    info_log.truncate(written as usize);
+    String::from_utf8_lossy(&info_log).trim_end().to_string()
+}
+
+impl ShaderBuilder {
+    pub unsafe fn new() -> ShaderBuilder {
+        ShaderBuilder {
+            program_id: gl::CreateProgram(),
+            sources: vec![],
+            defines: vec![],
+            stages: vec![],
+            files: vec![],
+            cache_directory: None,
+        }
+    }
+
+    // Defines `name` as `value` in the files attached after this
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Caches the linked program binary in `directory`, and loads it from there instead of compiling
+    // when it is current. Ignored where the driver does not hand out program binaries.
+    pub fn binary_cache<P: AsRef<Path>>(mut self, directory: P) -> ShaderBuilder {
+        self.cache_directory = Some(directory.as_ref().to_path_buf());
+        self
+    }
+
+    // Preprocesses the file of a stage. Like all the stages, it is compiled by `link`.
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> Result<ShaderBuilder, ShaderError> {
+        let path = Path::new(shader_path);
+        let shader_type = match path.extension().and_then(ShaderType::from_ext) {
+            Some(shader_type) => shader_type,
+            None => {
+                self.discard(&[]);
+                return Err(ShaderError::UnknownExtension(shader_path.to_string()));
+            }
+        };
+        let preprocessed = match preprocess::preprocess(shader_path, &self.defines) {
+            Ok(preprocessed) => preprocessed,
+            Err(e) => {
+                self.discard(&[]);
+                return Err(e);
+            }
+        };
+
+        self.sources.push(Source {
+            text         : preprocessed.source,
+            shader_type,
+            file         : shader_path.to_string(),
+            source_files : preprocessed.files.iter().map(|file| file.path.clone()).collect(),
+        });
+        self.stages.push(shader_path.to_string());
+        for file in preprocessed.files {
+            if !self.files.iter().any(|watched| watched.path == file.path) {
+                self.files.push(file);
+            }
+        }
+        Ok(self)
+    }
+
+    // Adds the source of a stage as is, without preprocessing. Like all the stages, it is compiled by `link`.
+    pub fn add_source(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
+        self.sources.push(Source {
+            text         : shader_src.to_string(),
+            shader_type,
+            file         : "<source>".to_string(),
+            source_files : vec!["<source>".to_string()],
+        });
+        self
+    }
+
+    // The former name of `add_source`, kept for the code written against it
+    #[deprecated(note = "use `add_source`, the stages are all compiled by `link`")]
+    pub unsafe fn compile_shader(self, shader_src: &str, shader_type: ShaderType) -> Result<ShaderBuilder, ShaderError> {
+        Ok(self.add_source(shader_src, shader_type))
+    }
+
+    // Compiles one stage, naming its source strings by the files they came from in errors
+    unsafe fn compile(source: &Source) -> Result<u32, ShaderError> {
+        let c_str_shader = match CString::new(source.text.as_bytes()) {
+            Ok(c_str_shader) => c_str_shader,
+            Err(e) => {
+                let log = format!("{}: contains a NUL byte at offset {}", source.file, e.nul_position());
+                return Err(ShaderError::Compile { stage: source.shader_type, file: source.file.clone(), log });
+            }
+        };
+        let shader = gl::CreateShader(source.shader_type.into());
+        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
+        gl::CompileShader(shader);
+
+        let mut success = i32::from(gl::FALSE);
+        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            let source_files: Vec<&str> = source.source_files.iter().map(String::as_str).collect();
+            let log = map_log(&shader_info_log(shader), &source_files);
+            gl::DeleteShader(shader);
+            return Err(ShaderError::Compile { stage: source.shader_type, file: source.file.clone(), log });
+        }
+        Ok(shader)
+    }
+
+    // Deletes the program and the shaders compiled so far, when giving up on building it
+    unsafe fn discard(self, shaders: &[u32]) {
+        for &shader in shaders {
+            gl::DeleteShader(shader);
+        }
+        gl::DeleteProgram(self.program_id);
+    }
+
+    pub unsafe fn link(self) -> Result<Shader, ShaderError> {
+        let cache = match &self.cache_directory {
+            Some(directory) if binary_cache::supported() => {
+                let names: Vec<String> = self.sources.iter().map(Source::cache_name).collect();
+                let files: Vec<&str> = names.iter().map(String::as_str).collect();
+                let texts: Vec<&str> = self.sources.iter().map(|source| source.text.as_str()).collect();
+                Some((binary_cache::cache_path(directory, &files, &self.defines), binary_cache::key(&texts)))
+            }
+            _ => None,
+        };
+        if let Some((path, key)) = &cache {
+            match binary_cache::read(path, *key) {
+                Ok((format, binary)) if binary_cache::load(self.program_id, format, &binary) => return Ok(self.into_shader()),
+                Ok(_) => println!("Ignoring program binary {}: the driver rejected it", path.display()),
+                Err(e) if binary_cache::is_absent(&e) => { },
+                Err(e) => println!("Ignoring program binary {}: {}", path.display(), e),
+            }
+        }
+
+        let mut shaders = vec![];
+        for source in &self.sources {
+            match ShaderBuilder::compile(source) {
+                Ok(shader) => shaders.push(shader),
+                Err(e) => {
+                    self.discard(&shaders);
+                    return Err(e);
+                }
+            }
+        }
+        for &shader in &shaders {
+            gl::AttachShader(self.program_id, shader);
+        }
+        if cache.is_some() {
+            binary_cache::retrievable(self.program_id);
+        }
+        gl::LinkProgram(self.program_id);
+
+        let mut success = i32::from(gl::FALSE);
+        gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            let log = program_info_log(self.program_id);
+            self.discard(&shaders);
+            return Err(ShaderError::Link { log });
+        }
+
+        for &shader in &shaders {
+            gl::DetachShader(self.program_id, shader);
+            gl::DeleteShader(shader);
+        }
+        if let Some((path, key)) = &cache {
+            if let Err(e) = binary_cache::write(path, *key, self.program_id) {
+                println!("Could not write program binary {}: {}", path.display(), e);
+            }
+        }
+        Ok(self.into_shader())
+    }
+
+    // The program, once linked
+    unsafe fn into_shader(self) -> Shader {
+        let compute = self.sources.iter().any(|source| source.shader_type == ShaderType::Compute);
+        Shader {
+            reflection: reflect::reflect(self.program_id, compute),
+            reported: RefCell::new(HashSet::new()),
+            program_id: self.program_id,
+            stages: self.stages,
+            defines: self.defines,
+            files: self.files,
+            cache_directory: self.cache_directory,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_location_reads_each_driver() {
+        assert_eq!(log_location("0:12(5): error: `color' undeclared"), Some((0, 12, 0..4)));
+        assert_eq!(log_location("1(7) : error C1008: undefined variable \"color\""), Some((1, 7, 0..4)));
+        assert_eq!(log_location("ERROR: 2:31: 'color' : undeclared identifier"), Some((2, 31, 7..11)));
+        assert_eq!(log_location("WARNING: 0:3: extension not supported"), Some((0, 3, 9..12)));
+
+        assert_eq!(log_location("ERROR: 1 compilation errors.  No code generated."), None);
+        assert_eq!(log_location("1(7 : error"), None);
+        assert_eq!(log_location(""), None);
+    }
+
+    #[test]
+    fn string_sources_are_cached_apart() {
+        let source = |text: &str| Source {
+            text         : text.to_string(),
+            shader_type  : ShaderType::Fragment,
+            file         : "<source>".to_string(),
+            source_files : vec!["<source>".to_string()],
+        };
+        let red = source("void main() { color = vec4(1, 0, 0, 1); }");
+        let green = source("void main() { color = vec4(0, 1, 0, 1); }");
+        assert_ne!(red.cache_name(), green.cache_name());
+        assert_eq!(red.cache_name(), source(&red.text).cache_name());
+
+        let file = Source { file: "shaders/simple.frag".to_string(), ..source("") };
+        assert_eq!(file.cache_name(), "shaders/simple.frag");
+    }
+
+    #[test]
+    fn map_log_names_files() {
+        let files = ["shaders/simple.vert", "shaders/lighting.glsl"];
+        let log = ["0:12(5): error: `color' undeclared",
+                   "1(7) : error C1008: undefined variable \"color\"",
+                   "ERROR: 1:31: 'color' : undeclared identifier",
+                   "ERROR: 5:2: source string out of range",
+                   "ERROR: 2 compilation errors.  No code generated."].join("\n");
+        assert_eq!(map_log(&log, &files), ["shaders/simple.vert:12(5): error: `color' undeclared",
+                                           "shaders/lighting.glsl:7 : error C1008: undefined variable \"color\"",
+                                           "ERROR: shaders/lighting.glsl:31: 'color' : undeclared identifier",
+                                           "ERROR: 5:2: source string out of range",
+                                           "ERROR: 2 compilation errors.  No code generated."].join("\n"));
+    }
+
+    // A program with no OpenGL object behind it, using the uniforms given
+    fn reflected(stages: &[&str], uniforms: &[(&str, u32)]) -> Shader {
+        Shader {
+            program_id      : 3,
+            stages          : stages.iter().map(|stage| stage.to_string()).collect(),
+            defines         : vec![],
+            files           : vec![],
+            reflection      : reflect::Reflection {
+                uniforms        : uniforms.iter().enumerate()
+                    .map(|(location, &(name, gl_type))| (name.to_string(), ActiveUniform { location: location as i32, gl_type, size: 1 }))
+                    .collect(),
+                attributes      : vec![],
+                blocks          : Default::default(),
+                storage_blocks  : Default::default(),
+                work_group_size : None,
+            },
+            reported        : RefCell::new(HashSet::new()),
+            cache_directory : None,
+        }
+    }
+
+    #[test]
+    fn checked_location_wants_the_declared_type() {
+        let shader = reflected(&["simple.vert", "simple.frag"], &[("model", gl::FLOAT_MAT4), ("time", gl::FLOAT)]);
+        assert_eq!(shader.checked_location("model", &[gl::FLOAT_MAT4], "mat4"), Some(0));
+        assert_eq!(shader.checked_location("time", &[gl::FLOAT], "float"), Some(1));
+        assert_eq!(shader.checked_location("time", &[gl::FLOAT_VEC3], "vec3"), None);
+        assert_eq!(shader.checked_location("camera", &[gl::FLOAT_VEC3], "vec3"), None);
+        assert!(shader.reported.borrow().contains("time") && shader.reported.borrow().contains("camera"));
+        assert!(!shader.reported.borrow().contains("model"));
+    }
+
+    #[test]
+    fn misuse_is_reported_once_per_name() {
+        let shader = reflected(&["simple.vert", "simple.frag"], &[("time", gl::FLOAT)]);
+        assert_eq!(shader.misuse("time", "vec3").as_deref(), Some("simple.vert, simple.frag: uniform time is declared float but set as vec3"));
+        assert_eq!(shader.misuse("time", "mat4"), None);
+        assert_eq!(shader.misuse("camera", "vec3").as_deref(), Some("simple.vert, simple.frag: no active uniform camera to set as vec3"));
+        assert_eq!(shader.misuse("camera", "vec3"), None);
+
+        let from_strings = reflected(&[], &[]);
+        assert_eq!(from_strings.misuse("albedo", "sampler").as_deref(), Some("program 3: no active uniform albedo to set as sampler"));
+    }
+
+    #[test]
+    fn changed_files_finds_modified_includes() {
+        let directory = std::env::temp_dir().join(format!("gloom-changed-files-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |file: &str| directory.join(file).to_string_lossy().into_owned();
+        std::fs::write(path("simple.frag"), "#version 430 core\n#include \"frame.glsl\"\nvoid main() { }\n").unwrap();
+        std::fs::write(path("frame.glsl"), "uniform float time;\n").unwrap();
+        let touch = |file: &str, seconds: u64| {
+            let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            std::fs::File::options().write(true).open(path(file)).unwrap().set_modified(time).unwrap();
+        };
+        touch("simple.frag", 1_000);
+        touch("frame.glsl", 1_000);
+
+        let mut files = preprocess::preprocess(&path("simple.frag"), &[]).unwrap().files;
+        assert_eq!(files.len(), 2);
+        assert!(changed_files(&mut files).is_empty());
+
+        touch("frame.glsl", 2_000);
+        assert_eq!(changed_files(&mut files), [path("frame.glsl")]);
+        assert!(changed_files(&mut files).is_empty());
+
+        std::fs::remove_file(path("simple.frag")).unwrap();
+        assert_eq!(changed_files(&mut files), [path("simple.frag")]);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
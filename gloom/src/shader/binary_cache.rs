@@ -0,0 +1,216 @@
+// Cache of linked program binaries, so programs are not compiled again on every launch.
+//
+// Each program is a file of its own in the cache directory, named by a hash of the files of its stages,
+// or of the source of stages given as strings, and its defines. Layout, all little endian:
+//   magic         8 bytes, "GLOOMPRG"
+//   version       u32
+//   key           u64
+//   binary format u32, as the driver names it
+//   binary length u64
+//   binary
+//
+// The key hashes the source of every stage after preprocessing, so the included files and the defines
+// too, and the vendor, renderer and version of the driver. A cache whose key does not match is stale,
+// and the program is compiled and the cache rewritten. Even a current binary may be rejected by the
+// driver, which is then treated the same.
+
+use crate::util;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"GLOOMPRG";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    NotACache,
+    UnsupportedVersion(u32),
+    Stale,
+    Truncated,
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CacheError::Io(e)                   => write!(f, "{}", e),
+            CacheError::NotACache               => write!(f, "not a program binary cache"),
+            CacheError::UnsupportedVersion(v)   => write!(f, "unsupported version {}, expected {}", v, VERSION),
+            CacheError::Stale                   => write!(f, "sources or driver have changed"),
+            CacheError::Truncated               => write!(f, "file is truncated"),
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
+
+// Whether the driver can hand out program binaries at all
+pub unsafe fn supported() -> bool {
+    if !gl::GetProgramBinary::is_loaded() || !gl::ProgramBinary::is_loaded() {
+        return false;
+    }
+    let mut formats = 0;
+    gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+    formats > 0
+}
+
+// The file caching the program built from `files` with `defines`, where stages given as strings are
+// named by a hash of their source
+pub fn cache_path(directory: &Path, files: &[&str], defines: &[(String, String)]) -> PathBuf {
+    let mut name = files.join("\n");
+    for (define, value) in defines {
+        name.push_str(&format!("\n{}={}", define, value));
+    }
+    directory.join(format!("{:016x}.bin", util::checksum(name.as_bytes())))
+}
+
+// The key of the program built from `sources`, with the driver it is built by
+pub unsafe fn key(sources: &[&str]) -> u64 {
+    let mut data = Vec::new();
+    for name in [gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        data.extend_from_slice(util::get_gl_string(name).as_bytes());
+        data.push(0);
+    }
+    for source in sources {
+        data.extend_from_slice(source.as_bytes());
+        data.push(0);
+    }
+    util::checksum(&data)
+}
+
+// Reads the binary format and the binary cached at `path`, if its key is `key`
+pub fn read(path: &Path, key: u64) -> Result<(u32, Vec<u8>), CacheError> {
+    let data = std::fs::read(path)?;
+    let header = 8 + 4 + 8 + 4 + 8;
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err(CacheError::NotACache);
+    }
+    if data.len() < header {
+        return Err(CacheError::Truncated);
+    }
+    let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(CacheError::UnsupportedVersion(version));
+    }
+    if u64::from_le_bytes(data[12..20].try_into().unwrap()) != key {
+        return Err(CacheError::Stale);
+    }
+    let format = u32::from_le_bytes(data[20..24].try_into().unwrap());
+    let length = u64::from_le_bytes(data[24..32].try_into().unwrap()) as usize;
+    let binary = data.get(header..header.saturating_add(length)).ok_or(CacheError::Truncated)?;
+    Ok((format, binary.to_vec()))
+}
+
+// Writes the binary of a linked program, which must have been linked with
+// gl::PROGRAM_BINARY_RETRIEVABLE_HINT set. It is written to a temporary file first and then moved in
+// place, so a cache is never left half written.
+pub unsafe fn write(path: &Path, key: u64, program_id: u32) -> Result<(), CacheError> {
+    let mut length = 0;
+    gl::GetProgramiv(program_id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+    let mut binary = vec![0u8; length.max(0) as usize];
+    let mut written = 0;
+    let mut format = 0;
+    gl::GetProgramBinary(program_id, length, &mut written, &mut format, binary.as_mut_ptr() as *mut _);
+    binary.truncate(written.max(0) as usize);
+    if binary.is_empty() {
+        return Err(CacheError::Truncated);
+    }
+    store(path, key, format, &binary)
+}
+
+// Writes a cache of `binary`, the part of `write` that needs no OpenGL context
+fn store(path: &Path, key: u64, format: u32, binary: &[u8]) -> Result<(), CacheError> {
+    let mut data = Vec::with_capacity(32 + binary.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&key.to_le_bytes());
+    data.extend_from_slice(&format.to_le_bytes());
+    data.extend_from_slice(&(binary.len() as u64).to_le_bytes());
+    data.extend_from_slice(binary);
+
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    let temporary = path.with_extension("bin.tmp");
+    File::create(&temporary)?.write_all(&data)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+// Loads a cached binary into a program, returning whether the driver accepted it
+pub unsafe fn load(program_id: u32, format: u32, binary: &[u8]) -> bool {
+    gl::ProgramBinary(program_id, format, binary.as_ptr() as *const _, binary.len() as i32);
+    let mut success = i32::from(gl::FALSE);
+    gl::GetProgramiv(program_id, gl::LINK_STATUS, &mut success);
+    success == i32::from(gl::TRUE)
+}
+
+// Asks the driver to keep the binary of a program retrievable, before it is linked
+pub unsafe fn retrievable(program_id: u32) {
+    gl::ProgramParameteri(program_id, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
+}
+
+// Whether the error is only that nothing was cached yet
+pub fn is_absent(error: &CacheError) -> bool {
+    matches!(error, CacheError::Io(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &[u8] = b"not really a program, but the driver's business";
+
+    // A cache file of its own for each test, in the temporary directory, removed on drop
+    struct Cache(PathBuf);
+
+    impl Cache {
+        fn new(name: &str) -> Cache {
+            Cache(std::env::temp_dir().join(format!("gloom-{}-{}.bin", name, std::process::id())))
+        }
+    }
+
+    impl Drop for Cache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let cache = Cache::new("program-round-trip");
+        assert!(matches!(read(&cache.0, 7), Err(ref e) if is_absent(e)));
+        store(&cache.0, 7, 0x8740, BINARY).unwrap();
+        let (format, binary) = read(&cache.0, 7).unwrap();
+        assert_eq!(format, 0x8740);
+        assert_eq!(binary, BINARY);
+        assert!(!cache.0.with_extension("bin.tmp").exists());
+    }
+
+    #[test]
+    fn stale_key_is_rejected() {
+        let cache = Cache::new("program-stale");
+        store(&cache.0, 7, 0x8740, BINARY).unwrap();
+        assert!(matches!(read(&cache.0, 8), Err(CacheError::Stale)));
+    }
+
+    #[test]
+    fn truncated_cache_is_rejected() {
+        let cache = Cache::new("program-truncated");
+        store(&cache.0, 7, 0x8740, BINARY).unwrap();
+        let data = std::fs::read(&cache.0).unwrap();
+
+        std::fs::write(&cache.0, &data[..data.len() - 4]).unwrap();
+        assert!(matches!(read(&cache.0, 7), Err(CacheError::Truncated)));
+        std::fs::write(&cache.0, &data[..20]).unwrap();
+        assert!(matches!(read(&cache.0, 7), Err(CacheError::Truncated)));
+        std::fs::write(&cache.0, &data[..4]).unwrap();
+        assert!(matches!(read(&cache.0, 7), Err(CacheError::NotACache)));
+    }
+}
@@ -0,0 +1,158 @@
+// A small preprocessor run over shader files before they reach the driver, which knows neither of:
+//   #include "common/lighting.glsl"   pastes in a file, relative to the directory of the including file
+//   defines given from Rust            written right after the #version line
+//
+// Each file is included at most once, however the paths leading to it are spelled, so shared files
+// need no include guards, and files including one another do not recurse. Every file read becomes
+// a source string of its own, and `#line` directives keep the line numbers of the driver's messages
+// true to the files they come from.
+
+use super::{modified, ShaderError, WatchedFile};
+use std::path::{Path, PathBuf};
+
+pub struct Preprocessed {
+    pub source : String,
+    pub files  : Vec<WatchedFile>, // Every file read, by the number of its source string
+    canonical  : Vec<PathBuf>,     // The same files, with their paths resolved for telling them apart
+}
+
+// The rest of a line after `#name`, which may have spaces after the #
+fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix(name)?;
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(c) if c.is_whitespace() || c == '"' => Some(rest.trim()),
+        Some(_) => None,
+    }
+}
+
+pub fn preprocess(path: &str, defines: &[(String, String)]) -> Result<Preprocessed, ShaderError> {
+    let mut preprocessed = Preprocessed { source: String::new(), files: vec![], canonical: vec![] };
+    append_file(&mut preprocessed, path, Some(defines))?;
+    Ok(preprocessed)
+}
+
+// The path with links, `.` and `..` resolved, or as it is when the file cannot be found
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+// Appends a file to the source, with the defines after its #version line when given
+fn append_file(out: &mut Preprocessed, path: &str, defines: Option<&[(String, String)]>) -> Result<(), ShaderError> {
+    let modified = modified(path);
+    let text = std::fs::read_to_string(path).map_err(|error| ShaderError::Io { path: path.to_string(), error })?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let number = out.files.len();
+    out.files.push(WatchedFile { path: path.to_string(), modified });
+    out.canonical.push(canonical(path));
+
+    let write_defines = |source: &mut String, next_line: usize| {
+        for (name, value) in defines.unwrap_or_default() {
+            source.push_str(&format!("#define {} {}\n", name, value));
+        }
+        source.push_str(&format!("#line {} {}\n", next_line, number));
+    };
+    // Without a #version line, which must come first, the defines go at the very start
+    if defines.is_some() && !text.lines().any(|line| directive(line, "version").is_some()) {
+        write_defines(&mut out.source, 1);
+    }
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        if let Some(name) = directive(line, "include") {
+            let name = name.strip_prefix('"').and_then(|name| name.strip_suffix('"')).ok_or_else(|| ShaderError::Include {
+                file    : path.to_string(),
+                line    : line_number,
+                message : format!("expected a file name in quotes, found {}", name),
+            })?;
+            let included = directory.join(name).to_string_lossy().to_string();
+            if !out.canonical.contains(&canonical(&included)) {
+                out.source.push_str(&format!("#line 1 {}\n", out.files.len()));
+                append_file(out, &included, None)?;
+            }
+            out.source.push_str(&format!("#line {} {}\n", line_number + 1, number));
+        } else if defines.is_some() && directive(line, "version").is_some() {
+            out.source.push_str(line);
+            out.source.push('\n');
+            write_defines(&mut out.source, line_number + 1);
+        } else {
+            out.source.push_str(line);
+            out.source.push('\n');
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of shader files of its own for each test, in the temporary directory, removed on drop
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new(name: &str, files: &[(&str, &str)]) -> Directory {
+            let directory = std::env::temp_dir().join(format!("gloom-{}-{}", name, std::process::id()));
+            for (path, contents) in files {
+                let path = directory.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+            Directory(directory)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn includes_once_with_line_directives() {
+        let directory = Directory::new("includes", &[
+            ("shader.vert", "#version 430 core\n#include \"common/a.glsl\"\n#include \"common/../common/a.glsl\"\nvoid main() {}\n"),
+            ("common/a.glsl", "#include \"b.glsl\"\nfloat a() { return b(); }\n"),
+            ("common/b.glsl", "#include \"a.glsl\"\nfloat b() { return 1.0; }\n"),
+        ]);
+        let preprocessed = preprocess(&directory.path("shader.vert"), &[("X".to_string(), "1".to_string())]).unwrap();
+
+        assert_eq!(preprocessed.source, [
+            "#version 430 core",
+            "#define X 1",
+            "#line 2 0",
+            "#line 1 1",
+            "#line 1 2",
+            "#line 2 2",
+            "float b() { return 1.0; }",
+            "#line 2 1",
+            "float a() { return b(); }",
+            "#line 3 0",
+            "#line 4 0",
+            "void main() {}",
+            "",
+        ].join("\n"));
+        let files: Vec<&str> = preprocessed.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(files, [directory.path("shader.vert"), directory.path("common/a.glsl"), directory.path("common/b.glsl")]);
+    }
+
+    #[test]
+    fn defines_without_version_go_first() {
+        let directory = Directory::new("no-version", &[("shader.frag", "void main() {}\n")]);
+        let preprocessed = preprocess(&directory.path("shader.frag"), &[("UNLIT".to_string(), "1".to_string())]).unwrap();
+        assert_eq!(preprocessed.source, "#define UNLIT 1\n#line 1 0\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn include_needs_quotes() {
+        let directory = Directory::new("unquoted", &[("shader.frag", "#version 430 core\n\n#include <lighting.glsl>\n")]);
+        match preprocess(&directory.path("shader.frag"), &[]) {
+            Err(ShaderError::Include { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("expected an include error"),
+        }
+    }
+}
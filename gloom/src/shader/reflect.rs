@@ -0,0 +1,214 @@
+// What a linked program turned out to use, asked of the driver once after linking: its plain
+// uniforms, its vertex inputs and its uniform blocks. Everything the driver optimized away is absent.
+
+use std::{collections::HashMap, ffi::CString, ptr};
+
+// A vertex shader input the linked program actually reads
+pub struct ActiveAttribute {
+    pub name     : String,
+    pub location : i32, // -1 for built-in inputs
+    pub gl_type  : u32, // gl::FLOAT_VEC3 and the like
+}
+
+// A uniform outside of any block, set with glUniform* or glProgramUniform*
+pub struct ActiveUniform {
+    pub location : i32,
+    pub gl_type  : u32, // gl::FLOAT_MAT4, gl::SAMPLER_2D and the like
+    pub size     : i32, // Elements, for arrays
+}
+
+// A uniform block the linked program uses, and where it puts each member
+pub struct ActiveUniformBlock {
+    pub binding   : i32,
+    pub data_size : usize,              // Bytes the buffer bound to it must hold at least
+    pub members   : Vec<BlockMember>,
+}
+
+// A shader storage block, read and written through the buffer bound to it
+pub struct ActiveStorageBlock {
+    pub binding   : i32,
+    pub data_size : usize, // Bytes up to any array of unsized length at its end
+}
+
+pub struct BlockMember {
+    pub name   : String, // Elements of arrays of structs are named apart, like lights[0].color
+    pub offset : i32,    // Bytes from the start of the block
+}
+
+pub struct Reflection {
+    pub uniforms        : HashMap<String, ActiveUniform>,      // Arrays under both `name` and `name[0]`
+    pub attributes      : Vec<ActiveAttribute>,
+    pub blocks          : HashMap<String, ActiveUniformBlock>,
+    pub storage_blocks  : HashMap<String, ActiveStorageBlock>, // Empty before OpenGL 4.3
+    pub work_group_size : Option<[u32; 3]>,                    // Of compute programs only
+}
+
+// A name the driver writes into a buffer of `max_length` bytes, `length` of them used
+unsafe fn read_name(max_length: i32, read: impl FnOnce(i32, *mut i32, *mut gl::types::GLchar)) -> String {
+    let mut name = vec![0u8; max_length.max(1) as usize];
+    let mut length = 0;
+    read(max_length.max(1), &mut length, name.as_mut_ptr() as *mut gl::types::GLchar);
+    name.truncate(length as usize);
+    String::from_utf8_lossy(&name).to_string()
+}
+
+// `compute` tells whether the program has a compute stage, which is all it may have then
+pub unsafe fn reflect(program_id: u32, compute: bool) -> Reflection {
+    Reflection {
+        uniforms        : uniforms(program_id),
+        attributes      : attributes(program_id),
+        blocks          : blocks(program_id),
+        storage_blocks  : storage_blocks(program_id),
+        work_group_size : if compute { Some(work_group_size(program_id)) } else { None },
+    }
+}
+
+unsafe fn uniforms(program_id: u32) -> HashMap<String, ActiveUniform> {
+    let mut count = 0;
+    let mut max_length = 0;
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORMS, &mut count);
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+
+    let mut uniforms = HashMap::new();
+    for index in 0..count as u32 {
+        let mut block = -1;
+        gl::GetActiveUniformsiv(program_id, 1, &index, gl::UNIFORM_BLOCK_INDEX, &mut block);
+        if block != -1 {
+            continue; // Set through the buffer bound to the block
+        }
+
+        let mut size = 0;
+        let mut gl_type = 0;
+        let name = read_name(max_length, |max_length, length, name| {
+            gl::GetActiveUniform(program_id, index, max_length, length, &mut size, &mut gl_type, name)
+        });
+        let name_cstr = CString::new(name.as_str()).expect("CString::new failed");
+        let location = gl::GetUniformLocation(program_id, name_cstr.as_ptr());
+
+        if let Some(array) = name.strip_suffix("[0]") {
+            uniforms.insert(array.to_string(), ActiveUniform { location, gl_type, size });
+        }
+        uniforms.insert(name, ActiveUniform { location, gl_type, size });
+    }
+    uniforms
+}
+
+unsafe fn attributes(program_id: u32) -> Vec<ActiveAttribute> {
+    let mut count = 0;
+    let mut max_length = 0;
+    gl::GetProgramiv(program_id, gl::ACTIVE_ATTRIBUTES, &mut count);
+    gl::GetProgramiv(program_id, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+
+    (0..count as u32).map(|index| {
+        let mut size = 0;
+        let mut gl_type = 0;
+        let name = read_name(max_length, |max_length, length, name| {
+            gl::GetActiveAttrib(program_id, index, max_length, length, &mut size, &mut gl_type, name)
+        });
+        let name_cstr = CString::new(name.as_str()).expect("CString::new failed");
+        let location = gl::GetAttribLocation(program_id, name_cstr.as_ptr());
+        ActiveAttribute { name, location, gl_type }
+    }).collect()
+}
+
+unsafe fn blocks(program_id: u32) -> HashMap<String, ActiveUniformBlock> {
+    let mut count = 0;
+    let mut max_length = 0;
+    let mut max_member_length = 0;
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORM_BLOCK_MAX_NAME_LENGTH, &mut max_length);
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_member_length);
+
+    (0..count as u32).map(|index| {
+        let name = read_name(max_length, |max_length, length, name| {
+            gl::GetActiveUniformBlockName(program_id, index, max_length, length, name)
+        });
+
+        let mut binding = 0;
+        let mut data_size = 0;
+        let mut member_count = 0;
+        gl::GetActiveUniformBlockiv(program_id, index, gl::UNIFORM_BLOCK_BINDING, &mut binding);
+        gl::GetActiveUniformBlockiv(program_id, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut data_size);
+        gl::GetActiveUniformBlockiv(program_id, index, gl::UNIFORM_BLOCK_ACTIVE_UNIFORMS, &mut member_count);
+        let mut indices = vec![0i32; member_count as usize];
+        let mut offsets = vec![0i32; member_count as usize];
+        if member_count > 0 {
+            gl::GetActiveUniformBlockiv(program_id, index, gl::UNIFORM_BLOCK_ACTIVE_UNIFORM_INDICES, indices.as_mut_ptr());
+            gl::GetActiveUniformsiv(program_id, member_count, indices.as_ptr() as *const u32, gl::UNIFORM_OFFSET, offsets.as_mut_ptr());
+        }
+
+        let members = indices.iter().zip(offsets).map(|(&uniform, offset)| {
+            let name = read_name(max_member_length, |max_length, length, name| {
+                gl::GetActiveUniformName(program_id, uniform as u32, max_length, length, name)
+            });
+            BlockMember { name, offset }
+        }).collect();
+
+        (name, ActiveUniformBlock { binding, data_size: data_size as usize, members })
+    }).collect()
+}
+
+unsafe fn storage_blocks(program_id: u32) -> HashMap<String, ActiveStorageBlock> {
+    if !gl::GetProgramInterfaceiv::is_loaded() {
+        return HashMap::new();
+    }
+    let mut count = 0;
+    let mut max_length = 0;
+    gl::GetProgramInterfaceiv(program_id, gl::SHADER_STORAGE_BLOCK, gl::ACTIVE_RESOURCES, &mut count);
+    gl::GetProgramInterfaceiv(program_id, gl::SHADER_STORAGE_BLOCK, gl::MAX_NAME_LENGTH, &mut max_length);
+
+    (0..count as u32).map(|index| {
+        let name = read_name(max_length, |max_length, length, name| {
+            gl::GetProgramResourceName(program_id, gl::SHADER_STORAGE_BLOCK, index, max_length, length, name)
+        });
+        let properties = [gl::BUFFER_BINDING, gl::BUFFER_DATA_SIZE];
+        let mut values = [0i32; 2];
+        gl::GetProgramResourceiv(program_id, gl::SHADER_STORAGE_BLOCK, index, properties.len() as i32, properties.as_ptr(),
+                                 values.len() as i32, ptr::null_mut(), values.as_mut_ptr());
+        (name, ActiveStorageBlock { binding: values[0], data_size: values[1] as usize })
+    }).collect()
+}
+
+unsafe fn work_group_size(program_id: u32) -> [u32; 3] {
+    let mut size = [0i32; 3];
+    gl::GetProgramiv(program_id, gl::COMPUTE_WORK_GROUP_SIZE, size.as_mut_ptr());
+    size.map(|n| n.max(1) as u32)
+}
+
+// The GLSL name of a uniform type, for messages
+pub fn type_name(gl_type: u32) -> String {
+    match gl_type {
+        gl::FLOAT                  => "float",
+        gl::FLOAT_VEC2             => "vec2",
+        gl::FLOAT_VEC3             => "vec3",
+        gl::FLOAT_VEC4             => "vec4",
+        gl::FLOAT_MAT3             => "mat3",
+        gl::FLOAT_MAT4             => "mat4",
+        gl::INT                    => "int",
+        gl::INT_VEC2               => "ivec2",
+        gl::INT_VEC3               => "ivec3",
+        gl::INT_VEC4               => "ivec4",
+        gl::UNSIGNED_INT           => "uint",
+        gl::BOOL                   => "bool",
+        gl::SAMPLER_2D             => "sampler2D",
+        gl::SAMPLER_3D             => "sampler3D",
+        gl::SAMPLER_CUBE           => "samplerCube",
+        gl::SAMPLER_2D_ARRAY       => "sampler2DArray",
+        gl::SAMPLER_2D_SHADOW      => "sampler2DShadow",
+        _ => return format!("type 0x{:04x}", gl_type),
+    }.to_string()
+}
+
+// The texture target a sampler type reads from, None for types that are no samplers
+pub fn sampler_target(gl_type: u32) -> Option<u32> {
+    match gl_type {
+        gl::SAMPLER_1D => Some(gl::TEXTURE_1D),
+        gl::SAMPLER_2D | gl::SAMPLER_2D_SHADOW | gl::INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_2D => Some(gl::TEXTURE_2D),
+        gl::SAMPLER_3D => Some(gl::TEXTURE_3D),
+        gl::SAMPLER_CUBE | gl::SAMPLER_CUBE_SHADOW => Some(gl::TEXTURE_CUBE_MAP),
+        gl::SAMPLER_2D_ARRAY | gl::SAMPLER_2D_ARRAY_SHADOW => Some(gl::TEXTURE_2D_ARRAY),
+        gl::SAMPLER_2D_MULTISAMPLE => Some(gl::TEXTURE_2D_MULTISAMPLE),
+        gl::SAMPLER_BUFFER => Some(gl::TEXTURE_BUFFER),
+        _ => None,
+    }
+}
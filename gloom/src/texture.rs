@@ -0,0 +1,148 @@
+use std::os::raw::c_void;
+
+// Heightmaps are uploaded as single channel 16-bit textures, sampled as a normalized float in [0, 1].
+// They are kept on the CPU too, so meshes can be displaced there the way the shaders displace them, as
+// for ray casts against what is drawn. Albedo textures, the base color of a surface, are uploaded as
+// 8-bit RGBA with mipmaps, and repeat.
+
+pub struct Heightmap {
+    pub width  : u32,
+    pub height : u32,
+    pub texels : Vec<u16>, // Row by row, the first row at v = 0
+}
+
+impl Heightmap {
+    pub fn load(path: &str) -> Result<Heightmap, image::ImageError> {
+        let image = image::open(path)?.into_luma16();
+        let (width, height) = image.dimensions();
+        Ok(Heightmap { width, height, texels: image.into_raw() })
+    }
+
+    // Rolling hills of value noise, `size` texels square, the same for the same `seed`. Octaves of
+    // half the size and amplitude of the one before add detail.
+    pub fn generate(size: u32, seed: u32) -> Heightmap {
+        const OCTAVES: u32 = 5;
+        // A value in [0, 1] for each corner of the lattice of an octave
+        let lattice = |x: u32, y: u32, octave: u32| {
+            let mut h = x.wrapping_mul(0x27d4_eb2d) ^ y.wrapping_mul(0x1656_67b1) ^ octave.wrapping_mul(0x9e37_79b9) ^ seed;
+            h = (h ^ (h >> 15)).wrapping_mul(0x2c1b_3c6d);
+            h = (h ^ (h >> 12)).wrapping_mul(0x297a_2d39);
+            (h ^ (h >> 15)) as f32 / u32::MAX as f32
+        };
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+
+        let mut heights = Vec::with_capacity((size * size) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let (mut height, mut amplitude, mut total) = (0.0, 1.0, 0.0);
+                for octave in 0..OCTAVES {
+                    let cell = (size >> (octave + 2)).max(1) as f32;
+                    let (fx, fy) = (x as f32 / cell, y as f32 / cell);
+                    let (cx, cy) = (fx as u32, fy as u32);
+                    let (tx, ty) = (smooth(fx.fract()), smooth(fy.fract()));
+                    let top = lattice(cx, cy, octave) * (1.0 - tx) + lattice(cx + 1, cy, octave) * tx;
+                    let bottom = lattice(cx, cy + 1, octave) * (1.0 - tx) + lattice(cx + 1, cy + 1, octave) * tx;
+                    height += (top * (1.0 - ty) + bottom * ty) * amplitude;
+                    total += amplitude;
+                    amplitude *= 0.5;
+                }
+                heights.push((height / total * u16::MAX as f32).round() as u16);
+            }
+        }
+        Heightmap { width: size, height: size, texels: heights }
+    }
+
+    // The height at `uv`, in [0, 1], filtered as the texture samples it: linearly between the centers
+    // of the texels, and clamped to the edge outside of them
+    pub fn sample(&self, uv: glm::Vec2) -> f32 {
+        let texel = |x: i64, y: i64| {
+            let x = x.clamp(0, self.width as i64 - 1) as usize;
+            let y = y.clamp(0, self.height as i64 - 1) as usize;
+            self.texels[y * self.width as usize + x] as f32 / u16::MAX as f32
+        };
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+        let top = texel(x0, y0) * (1.0 - tx) + texel(x0 + 1, y0) * tx;
+        let bottom = texel(x0, y0 + 1) * (1.0 - tx) + texel(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    pub unsafe fn upload(&self) -> u32 {
+        create_heightmap(self.width, self.height, &self.texels)
+    }
+}
+
+pub unsafe fn load_albedo(path: &str) -> Result<u32, image::ImageError> {
+    let image = image::open(path)?.into_rgba8();
+    let (width, height) = image.dimensions();
+
+    let mut texture_id: u32 = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+    gl::TexImage2D(
+        gl::TEXTURE_2D, 0, gl::RGBA8 as i32,
+        width as i32, height as i32, 0,
+        gl::RGBA, gl::UNSIGNED_BYTE,
+        image.as_raw().as_ptr() as *const c_void,
+    );
+    gl::GenerateMipmap(gl::TEXTURE_2D);
+
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    Ok(texture_id)
+}
+
+unsafe fn create_heightmap(width: u32, height: u32, texels: &[u16]) -> u32 {
+    let mut texture_id: u32 = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+
+    // Rows of 16-bit texels are not necessarily 4-byte aligned
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 2);
+    gl::TexImage2D(
+        gl::TEXTURE_2D, 0, gl::R16 as i32,
+        width as i32, height as i32, 0,
+        gl::RED, gl::UNSIGNED_SHORT,
+        texels.as_ptr() as *const c_void,
+    );
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    texture_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampling_filters_between_texel_centers() {
+        let heightmap = Heightmap { width: 2, height: 1, texels: vec![0, u16::MAX] };
+        assert_eq!(heightmap.sample(glm::vec2(0.25, 0.5)), 0.0);
+        assert_eq!(heightmap.sample(glm::vec2(0.5, 0.5)), 0.5);
+        assert_eq!(heightmap.sample(glm::vec2(0.75, 0.5)), 1.0);
+        assert_eq!(heightmap.sample(glm::vec2(-1.0, 0.5)), 0.0);
+        assert_eq!(heightmap.sample(glm::vec2(2.0, 3.0)), 1.0);
+    }
+
+    #[test]
+    fn generated_heightmaps_vary_and_repeat() {
+        let heightmap = Heightmap::generate(64, 7);
+        assert_eq!(heightmap.texels.len(), 64 * 64);
+        let (min, max) = (heightmap.texels.iter().min().unwrap(), heightmap.texels.iter().max().unwrap());
+        assert!(max - min > u16::MAX / 4, "heights only range from {} to {}", min, max);
+        assert_eq!(Heightmap::generate(64, 7).texels, heightmap.texels);
+        assert_ne!(Heightmap::generate(64, 8).texels, heightmap.texels);
+    }
+}
@@ -0,0 +1,281 @@
+extern crate nalgebra_glm as glm;
+
+use crate::buffer::{Buffer, BufferUsage};
+use crate::shader::{ActiveUniformBlock, Shader};
+use std::marker::PhantomData;
+use std::{fmt, mem, slice};
+
+// Uniform blocks shared by all shader programs, laid out by the std140 rules on both sides.
+//
+// `FrameUniforms` holds what stays the same for a whole frame, such as the camera and the lights, and
+// `ObjectUniforms` what changes from one node to the next. Each is a #[repr(C)] struct padded by hand to
+// the std140 layout of the block of the same name in the shaders, and bound to a fixed binding point,
+// so setting a block once serves every program. As the padding is easy to get wrong, each struct lists
+// the offsets of its members, which `validate` compares with those the linked program reports.
+
+pub const MAX_LIGHTS: usize = 4;
+
+// The binding points, which must match the `binding` of the blocks in the shaders
+pub const FRAME_BINDING: u32 = 0;
+pub const OBJECT_BINDING: u32 = 1;
+
+// A struct holding the contents of a uniform block
+pub trait UniformBlock: Copy {
+    const NAME: &'static str; // Of the block in the shaders
+    const BINDING: u32;
+
+    // Each member as the shader program names it, with its offset in the struct
+    fn members() -> Vec<(String, usize)>;
+}
+
+// A directional light
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub direction : glm::Vec4, // The way the light travels, w unused
+    pub color     : glm::Vec4, // w unused
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FrameUniforms {
+    pub view            : glm::Mat4,
+    pub projection      : glm::Mat4,
+    pub view_projection : glm::Mat4,
+    pub camera_position : glm::Vec3, // In world coordinates
+    pub time            : f32,       // Seconds since the start of the program
+    pub light_count     : i32,
+    _padding            : [i32; 3],  // Arrays of structs start at a multiple of 16 bytes
+    pub lights          : [Light; MAX_LIGHTS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectUniforms {
+    pub model                 : glm::Mat4,
+    pub model_view_projection : glm::Mat4,
+    pub highlight             : f32,        // How much to brighten the node, 0 for not at all
+    _padding                  : [f32; 3],
+}
+
+impl FrameUniforms {
+    pub fn new(view: glm::Mat4, projection: glm::Mat4, camera_position: glm::Vec3, time: f32, lights: &[Light]) -> FrameUniforms {
+        let light_count = lights.len().min(MAX_LIGHTS);
+        let mut frame_lights = [Light { direction: glm::zero(), color: glm::zero() }; MAX_LIGHTS];
+        frame_lights[..light_count].copy_from_slice(&lights[..light_count]);
+        FrameUniforms {
+            view,
+            projection,
+            view_projection : projection * view,
+            camera_position,
+            time,
+            light_count     : light_count as i32,
+            _padding        : [0; 3],
+            lights          : frame_lights,
+        }
+    }
+}
+
+impl ObjectUniforms {
+    pub fn new(model: glm::Mat4, view_projection: &glm::Mat4, highlight: f32) -> ObjectUniforms {
+        ObjectUniforms {
+            model,
+            model_view_projection : view_projection * model,
+            highlight,
+            _padding              : [0.0; 3],
+        }
+    }
+}
+
+impl UniformBlock for FrameUniforms {
+    const NAME: &'static str = "Frame";
+    const BINDING: u32 = FRAME_BINDING;
+
+    fn members() -> Vec<(String, usize)> {
+        let mut members = vec![
+            ("view".to_string(), mem::offset_of!(FrameUniforms, view)),
+            ("projection".to_string(), mem::offset_of!(FrameUniforms, projection)),
+            ("view_projection".to_string(), mem::offset_of!(FrameUniforms, view_projection)),
+            ("camera_position".to_string(), mem::offset_of!(FrameUniforms, camera_position)),
+            ("time".to_string(), mem::offset_of!(FrameUniforms, time)),
+            ("light_count".to_string(), mem::offset_of!(FrameUniforms, light_count)),
+        ];
+        for i in 0..MAX_LIGHTS {
+            let light = mem::offset_of!(FrameUniforms, lights) + i * mem::size_of::<Light>();
+            members.push((format!("lights[{}].direction", i), light + mem::offset_of!(Light, direction)));
+            members.push((format!("lights[{}].color", i), light + mem::offset_of!(Light, color)));
+        }
+        members
+    }
+}
+
+impl UniformBlock for ObjectUniforms {
+    const NAME: &'static str = "Object";
+    const BINDING: u32 = OBJECT_BINDING;
+
+    fn members() -> Vec<(String, usize)> {
+        vec![
+            ("model".to_string(), mem::offset_of!(ObjectUniforms, model)),
+            ("model_view_projection".to_string(), mem::offset_of!(ObjectUniforms, model_view_projection)),
+            ("highlight".to_string(), mem::offset_of!(ObjectUniforms, highlight)),
+        ]
+    }
+}
+
+// A buffer holding one uniform block, bound to its binding point for as long as it lives
+pub struct UniformBuffer<T: UniformBlock> {
+    buffer : Buffer,
+    block  : PhantomData<T>,
+}
+
+impl<T: UniformBlock> UniformBuffer<T> {
+    pub unsafe fn new() -> UniformBuffer<T> {
+        let buffer = Buffer::new(gl::UNIFORM_BUFFER, &vec![0; mem::size_of::<T>()], BufferUsage::Dynamic);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, T::BINDING, buffer.id());
+        UniformBuffer { buffer, block: PhantomData }
+    }
+
+    // Replaces the contents of the block, for the draw calls from now on
+    pub unsafe fn set(&self, block: &T) {
+        let bytes = slice::from_raw_parts(block as *const T as *const u8, mem::size_of::<T>());
+        self.buffer.update(0, bytes);
+    }
+}
+
+// A difference between a struct and the uniform block of the same name in a shader program
+#[derive(Debug)]
+pub enum BlockMismatch {
+    Missing(&'static str),           // The program does not use the block
+    WrongBinding { block: &'static str, layout: u32, shader: i32 },
+    TooSmall { block: &'static str, layout: usize, shader: usize },
+    WrongOffset { name: String, layout: usize, shader: i32 },
+    NotInShader(String),             // A member of the struct the block does not have
+    NotInStruct(String),             // A member of the block the struct does not have
+}
+
+impl BlockMismatch {
+    // Whether the shader would read the wrong data. Programs are free to leave out blocks they do not need.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, BlockMismatch::Missing(_))
+    }
+}
+
+impl fmt::Display for BlockMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockMismatch::Missing(block) =>
+                write!(f, "the uniform block {} is not used by the shader", block),
+            BlockMismatch::WrongBinding { block, layout, shader } =>
+                write!(f, "the uniform block {} is bound to {}, but to {} in the shader", block, layout, shader),
+            BlockMismatch::TooSmall { block, layout, shader } =>
+                write!(f, "the uniform block {} takes {} bytes, but {} in the shader", block, layout, shader),
+            BlockMismatch::WrongOffset { name, layout, shader } =>
+                write!(f, "{} is at offset {}, but at {} in the shader", name, layout, shader),
+            BlockMismatch::NotInShader(name) =>
+                write!(f, "{} is not in the uniform block of the shader", name),
+            BlockMismatch::NotInStruct(name) =>
+                write!(f, "{} of the shader's uniform block is not in the struct", name),
+        }
+    }
+}
+
+// Compares the layout of `T` with its block in a linked shader program
+pub fn validate<T: UniformBlock>(shader: &Shader) -> Vec<BlockMismatch> {
+    match shader.uniform_block(T::NAME) {
+        Some(block) => compare::<T>(block),
+        None => vec![BlockMismatch::Missing(T::NAME)],
+    }
+}
+
+fn compare<T: UniformBlock>(block: &ActiveUniformBlock) -> Vec<BlockMismatch> {
+    let mut mismatches = vec![];
+    if block.binding != T::BINDING as i32 {
+        mismatches.push(BlockMismatch::WrongBinding { block: T::NAME, layout: T::BINDING, shader: block.binding });
+    }
+    if mem::size_of::<T>() < block.data_size {
+        mismatches.push(BlockMismatch::TooSmall { block: T::NAME, layout: mem::size_of::<T>(), shader: block.data_size });
+    }
+
+    let members = T::members();
+    for (name, offset) in &members {
+        match block.members.iter().find(|member| member.name == *name) {
+            Some(member) if member.offset != *offset as i32 => mismatches.push(BlockMismatch::WrongOffset {
+                name   : name.clone(),
+                layout : *offset,
+                shader : member.offset,
+            }),
+            Some(_) => { },
+            None => mismatches.push(BlockMismatch::NotInShader(name.clone())),
+        }
+    }
+    for member in &block.members {
+        if !members.iter().any(|(name, _)| *name == member.name) {
+            mismatches.push(BlockMismatch::NotInStruct(member.name.clone()));
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::BlockMember;
+
+    // The offsets the std140 rules give the blocks in the shaders: matrices and vec3 align to 16
+    // bytes, scalars to 4, and arrays of structs start at, and step by, multiples of 16
+    #[test]
+    fn frame_members_follow_std140() {
+        let mut expected = vec![
+            ("view".to_string(), 0),
+            ("projection".to_string(), 64),
+            ("view_projection".to_string(), 128),
+            ("camera_position".to_string(), 192),
+            ("time".to_string(), 204),
+            ("light_count".to_string(), 208),
+        ];
+        for i in 0..MAX_LIGHTS {
+            expected.push((format!("lights[{}].direction", i), 224 + 32 * i));
+            expected.push((format!("lights[{}].color", i), 240 + 32 * i));
+        }
+        assert_eq!(FrameUniforms::members(), expected);
+        assert_eq!(mem::size_of::<FrameUniforms>(), 224 + 32 * MAX_LIGHTS);
+    }
+
+    #[test]
+    fn object_members_follow_std140() {
+        assert_eq!(ObjectUniforms::members(), vec![
+            ("model".to_string(), 0),
+            ("model_view_projection".to_string(), 64),
+            ("highlight".to_string(), 128),
+        ]);
+        assert_eq!(mem::size_of::<ObjectUniforms>() % 16, 0);
+    }
+
+    fn block<T: UniformBlock>() -> ActiveUniformBlock {
+        ActiveUniformBlock {
+            binding   : T::BINDING as i32,
+            data_size : mem::size_of::<T>(),
+            members   : T::members().into_iter().map(|(name, offset)| BlockMember { name, offset: offset as i32 }).collect(),
+        }
+    }
+
+    #[test]
+    fn compare_finds_each_mismatch() {
+        assert!(compare::<ObjectUniforms>(&block::<ObjectUniforms>()).is_empty());
+
+        let mut shifted = block::<ObjectUniforms>();
+        shifted.binding = 5;
+        shifted.data_size += 16;
+        shifted.members[2].offset = 132;
+        shifted.members.push(BlockMember { name: "tint".to_string(), offset: 144 });
+        shifted.members.remove(0);
+        let mismatches = compare::<ObjectUniforms>(&shifted);
+        assert!(matches!(mismatches[0], BlockMismatch::WrongBinding { layout: OBJECT_BINDING, shader: 5, .. }));
+        assert!(matches!(mismatches[1], BlockMismatch::TooSmall { .. }));
+        assert!(matches!(&mismatches[2], BlockMismatch::NotInShader(name) if name == "model"));
+        assert!(matches!(&mismatches[3], BlockMismatch::WrongOffset { name, layout: 128, shader: 132 } if name == "highlight"));
+        assert!(matches!(&mismatches[4], BlockMismatch::NotInStruct(name) if name == "tint"));
+        assert_eq!(mismatches.len(), 5);
+        assert!(mismatches.iter().all(BlockMismatch::is_error));
+    }
+}
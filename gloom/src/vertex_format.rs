@@ -0,0 +1,299 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+use crate::permutations::Features;
+use crate::vertex_layout::{VertexAttribute, VertexLayout};
+
+// How the vertices of a mesh are laid out in GPU memory.
+//
+// The attributes are stored either in one buffer per attribute, or interleaved in a single buffer,
+// and each can use a smaller encoding than plain floats:
+//   positions and texture coordinates as half floats, padded to a multiple of 4 bytes
+//   colors as normalized unsigned bytes
+//   normals octahedral encoded, as two normalized shorts
+// Octahedral normals go to their own attribute location, which the vertex shaders read instead of the
+// plain normals when built with PACKED_NORMALS defined, see `features`. The names are those of the
+// inputs of the vertex shaders.
+
+pub const POSITION_LOCATION: u32 = 0;
+pub const COLOR_LOCATION: u32 = 1;
+pub const TEXCOORD_LOCATION: u32 = 2;
+pub const NORMAL_LOCATION: u32 = 3;
+pub const PACKED_NORMAL_LOCATION: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Precision {
+    Full, // f32
+    Half, // f16
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorFormat {
+    Float,  // 4 x f32
+    Unorm8, // 4 x u8, normalized to [0, 1]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NormalFormat {
+    Float,      // 3 x f32
+    Octahedral, // 2 x i16, normalized to [-1, 1]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VertexFormat {
+    pub positions   : Precision,
+    pub colors      : ColorFormat,
+    pub normals     : NormalFormat,
+    pub texcoords   : Option<Precision>, // None to leave them out, also when the mesh has none
+    pub interleaved : bool,              // One buffer for all attributes, rather than one each
+}
+
+impl VertexFormat {
+    // The layout of the original assignment code, 40 bytes per vertex
+    pub const FULL: VertexFormat = VertexFormat {
+        positions   : Precision::Full,
+        colors      : ColorFormat::Float,
+        normals     : NormalFormat::Float,
+        texcoords   : None,
+        interleaved : false,
+    };
+
+    // 16 bytes per vertex, plus 4 with texture coordinates. Half float positions have about three
+    // significant digits, so this suits models of modest extent around their origin.
+    pub const COMPACT: VertexFormat = VertexFormat {
+        positions   : Precision::Half,
+        colors      : ColorFormat::Unorm8,
+        normals     : NormalFormat::Octahedral,
+        texcoords   : Some(Precision::Half),
+        interleaved : true,
+    };
+
+    // The shader features reading vertices of this format, to select the variant drawing them
+    pub fn features(&self) -> Features {
+        match self.normals {
+            NormalFormat::Float      => Features::NONE,
+            NormalFormat::Octahedral => Features::PACKED_NORMALS,
+        }
+    }
+}
+
+// f32 to the bits of the nearest f16, rounding ties to even. Too large values become infinite.
+pub fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 }; // infinity or NaN
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or zero when even the largest mantissa would round away
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round_up as u32) as u16;
+    }
+
+    // Rounding up may carry into the exponent, which is exactly right, up to infinity
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    sign | (half + round_up as u32) as u16
+}
+
+// A unit normal folded onto the octahedron and flattened to two coordinates in [-1, 1]
+pub fn octahedral_encode(normal: &glm::Vec3) -> [f32; 2] {
+    let n = normal / (normal.x.abs() + normal.y.abs() + normal.z.abs()).max(f32::MIN_POSITIVE);
+    if n.z >= 0.0 {
+        [n.x, n.y]
+    } else {
+        let sign = |v: f32| if v >= 0.0 { 1.0 } else { -1.0 };
+        [(1.0 - n.y.abs()) * sign(n.x), (1.0 - n.x.abs()) * sign(n.y)]
+    }
+}
+
+// The unit normal of two octahedral coordinates, as the vertex shaders decode it
+pub fn octahedral_decode(encoded: [f32; 2]) -> glm::Vec3 {
+    let [x, y] = encoded;
+    let mut n = glm::vec3(x, y, 1.0 - x.abs() - y.abs());
+    if n.z < 0.0 {
+        let sign = |v: f32| if v >= 0.0 { 1.0 } else { -1.0 };
+        n.x = (1.0 - y.abs()) * sign(x);
+        n.y = (1.0 - x.abs()) * sign(y);
+    }
+    glm::normalize(&n)
+}
+
+fn snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8
+}
+
+// Writes `components` values of `data` for vertex `v`, or zeros when the mesh lacks them
+fn write_floats(out: &mut Vec<u8>, data: &[f32], v: usize, components: usize, precision: Precision) {
+    let values = data.get(v * components..(v + 1) * components);
+    for c in 0..components {
+        let value = values.map_or(0.0, |values| values[c]);
+        match precision {
+            Precision::Full => out.extend_from_slice(&value.to_le_bytes()),
+            Precision::Half => out.extend_from_slice(&half_bits(value).to_le_bytes()),
+        }
+    }
+}
+
+impl VertexFormat {
+    // The attributes stored for `mesh`
+    fn attributes(&self, mesh: &Mesh) -> Vec<VertexAttribute> {
+        let floats = |name, location, components, precision| match precision {
+            Precision::Full => VertexAttribute::new(name, location, components, gl::FLOAT, false),
+            Precision::Half => VertexAttribute::new(name, location, components, gl::HALF_FLOAT, false),
+        };
+
+        let mut attributes = vec![floats("position", POSITION_LOCATION, 3, self.positions)];
+        attributes.push(match self.colors {
+            ColorFormat::Float  => VertexAttribute::new("in_color", COLOR_LOCATION, 4, gl::FLOAT, false),
+            ColorFormat::Unorm8 => VertexAttribute::new("in_color", COLOR_LOCATION, 4, gl::UNSIGNED_BYTE, true),
+        });
+        attributes.push(match self.normals {
+            NormalFormat::Float      => VertexAttribute::new("in_normals", NORMAL_LOCATION, 3, gl::FLOAT, false),
+            NormalFormat::Octahedral => VertexAttribute::new("in_packed_normals", PACKED_NORMAL_LOCATION, 2, gl::SHORT, true),
+        });
+        if let Some(precision) = self.texcoords.filter(|_| !mesh.texcoords.is_empty()) {
+            attributes.push(floats("in_texcoords", TEXCOORD_LOCATION, 2, precision));
+        }
+        attributes
+    }
+
+    // How the vertices of `mesh` are laid out in buffers in this format
+    pub fn layout(&self, mesh: &Mesh) -> VertexLayout {
+        let attributes = self.attributes(mesh);
+        if self.interleaved {
+            VertexLayout::new().buffer(&attributes)
+        } else {
+            attributes.iter().fold(VertexLayout::new(), |layout, attribute| layout.buffer(&[*attribute]))
+        }
+    }
+
+    // Bytes per vertex of `mesh` in this format
+    pub fn vertex_size(&self, mesh: &Mesh) -> usize {
+        self.layout(mesh).vertex_size()
+    }
+
+    // Encodes one attribute of one vertex
+    fn write_attribute(&self, out: &mut Vec<u8>, attribute: &VertexAttribute, mesh: &Mesh, v: usize) {
+        let start = out.len();
+        match attribute.location {
+            POSITION_LOCATION => write_floats(out, &mesh.vertices, v, 3, self.positions),
+            TEXCOORD_LOCATION => write_floats(out, &mesh.texcoords, v, 2, self.texcoords.unwrap_or(Precision::Full)),
+            COLOR_LOCATION => match self.colors {
+                ColorFormat::Float  => write_floats(out, &mesh.colors, v, 4, Precision::Full),
+                ColorFormat::Unorm8 => out.extend((0..4).map(|c| unorm8(mesh.colors.get(v * 4 + c).cloned().unwrap_or(1.0)))),
+            },
+            _ => {
+                let normal = mesh.normals.get(v * 3..v * 3 + 3).map_or(glm::zero(), |n| glm::vec3(n[0], n[1], n[2]));
+                match self.normals {
+                    NormalFormat::Float => write_floats(out, &mesh.normals, v, 3, Precision::Full),
+                    NormalFormat::Octahedral => {
+                        for value in octahedral_encode(&normal).iter() {
+                            out.extend_from_slice(&snorm16(*value).to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+        out.resize(start + attribute.size, 0);
+    }
+
+    // The contents of the buffers of `layout(mesh)`, ready to upload
+    pub fn pack(&self, mesh: &Mesh) -> Vec<Vec<u8>> {
+        let vertex_count = mesh.vertices.len() / 3;
+        self.layout(mesh).buffers.iter().map(|buffer| {
+            let mut data = Vec::with_capacity(buffer.stride * vertex_count);
+            for v in 0..vertex_count {
+                for attribute in &buffer.attributes {
+                    self.write_attribute(&mut data, attribute, mesh, v);
+                }
+            }
+            data
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The value of f16 bits, to check `half_bits` against
+    fn half_value(bits: u16) -> f32 {
+        let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((bits >> 10) & 0x1f) as i32;
+        let mantissa = (bits & 0x3ff) as f32;
+        sign * match exponent {
+            0    => mantissa * 2f32.powi(-24),
+            0x1f => if mantissa == 0.0 { f32::INFINITY } else { f32::NAN },
+            _    => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    #[test]
+    fn half_bits_of_known_values() {
+        assert_eq!(half_bits(0.0), 0x0000);
+        assert_eq!(half_bits(-0.0), 0x8000);
+        assert_eq!(half_bits(1.0), 0x3c00);
+        assert_eq!(half_bits(-2.0), 0xc000);
+        assert_eq!(half_bits(65504.0), 0x7bff);
+        assert_eq!(half_bits(65520.0), 0x7c00); // rounds up to infinity
+        assert_eq!(half_bits(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(half_bits(f32::NAN) & 0x7c00, 0x7c00);
+        assert_eq!(half_bits(2f32.powi(-24)), 0x0001); // smallest subnormal
+        assert_eq!(half_bits(2f32.powi(-26)), 0x0000);
+        // Ties round to even
+        assert_eq!(half_bits(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(half_bits(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+    }
+
+    #[test]
+    fn half_bits_round_trip() {
+        for bits in 0..=u16::MAX {
+            let value = half_value(bits);
+            if !value.is_nan() {
+                assert_eq!(half_bits(value), bits, "{:#06x} is {}", bits, value);
+            }
+        }
+    }
+
+    #[test]
+    fn octahedral_round_trip() {
+        let axes = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, 0.0, -1.0)];
+        let spread = (0..500).map(|i| {
+            // A Fibonacci spiral over the sphere, reaching both hemispheres
+            let z = 1.0 - (i as f32 + 0.5) / 250.0;
+            let angle = i as f32 * 2.399_963;
+            let r = (1.0 - z * z).sqrt();
+            glm::vec3(r * angle.cos(), r * angle.sin(), z)
+        });
+        for normal in axes.iter().cloned().chain(spread) {
+            let encoded = octahedral_encode(&normal);
+            assert!(encoded.iter().all(|c| (-1.0..=1.0).contains(c)));
+            assert!(glm::distance(&octahedral_decode(encoded), &normal) < 1e-5, "{:?}", normal);
+
+            // Through the snorm16 the shaders read
+            let quantized = encoded.map(|c| snorm16(c) as f32 / i16::MAX as f32);
+            assert!(glm::dot(&octahedral_decode(quantized), &normal) > 0.999_99, "{:?}", normal);
+        }
+    }
+}
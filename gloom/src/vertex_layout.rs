@@ -0,0 +1,281 @@
+use crate::shader::{ActiveAttribute, Shader};
+use std::fmt;
+use std::os::raw::c_void;
+
+// Declarative description of the vertex attributes in a set of buffers, used to set up VAOs and to
+// check them against what a linked shader program actually reads.
+//
+//     let layout = VertexLayout::new()
+//         .buffer(&[VertexAttribute::new("position", 0, 3, gl::FLOAT, false),
+//                   VertexAttribute::new("in_color", 1, 4, gl::UNSIGNED_BYTE, true)])
+//         .buffer(&[VertexAttribute::new("in_normals", 3, 3, gl::FLOAT, false)]);
+//
+// Attributes given together share a buffer, interleaved in the order given.
+
+// One attribute, as the vertex shader declares it and as it is stored in its buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VertexAttribute {
+    pub name       : &'static str, // The `in` variable of the vertex shader
+    pub location   : u32,
+    pub components : i32,
+    pub gl_type    : u32,   // gl::FLOAT, gl::HALF_FLOAT, gl::UNSIGNED_BYTE, gl::SHORT, ...
+    pub normalized : bool,  // Whether integers are mapped to [0, 1] or [-1, 1], rather than converted as is
+    pub size       : usize, // Bytes per vertex, padded to a multiple of 4
+    pub offset     : usize, // Bytes from the start of a vertex in its buffer, set by VertexLayout::buffer
+}
+
+fn component_size(gl_type: u32) -> usize {
+    match gl_type {
+        gl::BYTE | gl::UNSIGNED_BYTE => 1,
+        gl::SHORT | gl::UNSIGNED_SHORT | gl::HALF_FLOAT => 2,
+        gl::DOUBLE => 8,
+        _ => 4,
+    }
+}
+
+impl VertexAttribute {
+    pub fn new(name: &'static str, location: u32, components: i32, gl_type: u32, normalized: bool) -> VertexAttribute {
+        VertexAttribute {
+            name,
+            location,
+            components,
+            gl_type,
+            normalized,
+            size   : (components as usize * component_size(gl_type)).next_multiple_of(4),
+            offset : 0,
+        }
+    }
+}
+
+// The attributes stored in one buffer
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BufferLayout {
+    pub stride     : usize,
+    pub attributes : Vec<VertexAttribute>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct VertexLayout {
+    pub buffers: Vec<BufferLayout>,
+}
+
+// A difference between a vertex layout and the attributes a shader program reads
+#[derive(Debug)]
+pub enum LayoutMismatch {
+    WrongLocation { name: &'static str, layout: u32, shader: i32 },
+    LocationTaken { location: u32, layout: &'static str, shader: String },
+    WrongBaseType { name: &'static str, shader: &'static str }, // Integers or doubles, which `apply` cannot feed
+    ExtraComponents { name: &'static str, layout: i32, shader: i32 },
+    NotRead(&'static str),           // The shader does not read this attribute of the layout
+    NotProvided(String, i32),        // The shader reads this attribute, but it is not in the layout
+}
+
+impl LayoutMismatch {
+    // Whether the shader would read the wrong data. Attributes left out on either side are not:
+    // those not provided read as zero, and those not read are ignored. Neither are extra components,
+    // which the shader leaves unread.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, LayoutMismatch::NotRead(_) | LayoutMismatch::NotProvided(..) | LayoutMismatch::ExtraComponents { .. })
+    }
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutMismatch::WrongLocation { name, layout, shader } =>
+                write!(f, "{} is at location {} in the layout, but at {} in the shader", name, layout, shader),
+            LayoutMismatch::LocationTaken { location, layout, shader } =>
+                write!(f, "location {} holds {} in the layout, but the shader reads {} there", location, layout, shader),
+            LayoutMismatch::WrongBaseType { name, shader } =>
+                write!(f, "{} is read as {} by the shader, but the layout converts it to float", name, shader),
+            LayoutMismatch::ExtraComponents { name, layout, shader } =>
+                write!(f, "{} has {} components in the layout, but the shader only reads {}", name, layout, shader),
+            LayoutMismatch::NotRead(name) =>
+                write!(f, "{} is not read by the shader", name),
+            LayoutMismatch::NotProvided(name, location) =>
+                write!(f, "{} at location {} is read by the shader, but not in the layout", name, location),
+        }
+    }
+}
+
+// Components of the scalar, vector and matrix types of vertex shader inputs, counting one column of matrices
+fn type_components(gl_type: u32) -> i32 {
+    match gl_type {
+        gl::FLOAT_VEC2 | gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2 | gl::DOUBLE_VEC2 | gl::FLOAT_MAT2 => 2,
+        gl::FLOAT_VEC3 | gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3 | gl::DOUBLE_VEC3 | gl::FLOAT_MAT3 => 3,
+        gl::FLOAT_VEC4 | gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4 | gl::DOUBLE_VEC4 | gl::FLOAT_MAT4 => 4,
+        _ => 1,
+    }
+}
+
+// The base type of vertex shader inputs that are not read as float, which VertexAttribPointer converts every attribute to
+fn non_float_base_type(gl_type: u32) -> Option<&'static str> {
+    match gl_type {
+        gl::INT | gl::INT_VEC2 | gl::INT_VEC3 | gl::INT_VEC4 => Some("int"),
+        gl::UNSIGNED_INT | gl::UNSIGNED_INT_VEC2 | gl::UNSIGNED_INT_VEC3 | gl::UNSIGNED_INT_VEC4 => Some("uint"),
+        gl::DOUBLE | gl::DOUBLE_VEC2 | gl::DOUBLE_VEC3 | gl::DOUBLE_VEC4 => Some("double"),
+        _ => None,
+    }
+}
+
+impl VertexLayout {
+    pub fn new() -> VertexLayout {
+        VertexLayout { buffers: vec![] }
+    }
+
+    // Adds a buffer holding `attributes`, interleaved in the order given
+    pub fn buffer(mut self, attributes: &[VertexAttribute]) -> VertexLayout {
+        let mut offset = 0;
+        let attributes = attributes.iter().map(|attribute| {
+            let attribute = VertexAttribute { offset, ..*attribute };
+            offset += attribute.size;
+            attribute
+        }).collect();
+        self.buffers.push(BufferLayout { stride: offset, attributes });
+        self
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = &VertexAttribute> {
+        self.buffers.iter().flat_map(|buffer| buffer.attributes.iter())
+    }
+
+    // Bytes per vertex, over all buffers
+    pub fn vertex_size(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.stride).sum()
+    }
+
+    // Points the attributes of the bound VAO at `buffer_ids`, one for each buffer of the layout
+    pub unsafe fn apply(&self, buffer_ids: &[u32]) {
+        assert_eq!(buffer_ids.len(), self.buffers.len(), "one buffer is needed for each buffer of the layout");
+        for (buffer, &buffer_id) in self.buffers.iter().zip(buffer_ids) {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+            for attribute in &buffer.attributes {
+                let normalized = if attribute.normalized { gl::TRUE } else { gl::FALSE };
+                gl::VertexAttribPointer(attribute.location, attribute.components, attribute.gl_type, normalized,
+                                        buffer.stride as i32, attribute.offset as *const c_void);
+                gl::EnableVertexAttribArray(attribute.location);
+            }
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    }
+
+    // Disables the attributes of the bound VAO that `apply` enabled
+    pub unsafe fn unapply(&self) {
+        for attribute in self.attributes() {
+            gl::DisableVertexAttribArray(attribute.location);
+        }
+    }
+
+    // Compares the layout with the active attributes of a linked shader program
+    pub fn validate(&self, shader: &Shader) -> Vec<LayoutMismatch> {
+        self.compare(shader.active_attributes())
+    }
+
+    fn compare(&self, active: &[ActiveAttribute]) -> Vec<LayoutMismatch> {
+        let mut mismatches = vec![];
+        for attribute in self.attributes() {
+            match active.iter().find(|a| a.name == attribute.name) {
+                Some(a) if a.location != attribute.location as i32 => mismatches.push(LayoutMismatch::WrongLocation {
+                    name   : attribute.name,
+                    layout : attribute.location,
+                    shader : a.location,
+                }),
+                Some(a) if non_float_base_type(a.gl_type).is_some() => mismatches.push(LayoutMismatch::WrongBaseType {
+                    name   : attribute.name,
+                    shader : non_float_base_type(a.gl_type).unwrap(),
+                }),
+                Some(a) if attribute.components > type_components(a.gl_type) => mismatches.push(LayoutMismatch::ExtraComponents {
+                    name   : attribute.name,
+                    layout : attribute.components,
+                    shader : type_components(a.gl_type),
+                }),
+                Some(_) => { },
+                None => mismatches.push(LayoutMismatch::NotRead(attribute.name)),
+            }
+        }
+        for a in active {
+            // Built-in inputs such as gl_VertexID have no location
+            if a.location < 0 || self.attributes().any(|attribute| attribute.name == a.name) {
+                continue;
+            }
+            match self.attributes().find(|attribute| attribute.location as i32 == a.location) {
+                Some(attribute) => mismatches.push(LayoutMismatch::LocationTaken {
+                    location : attribute.location,
+                    layout   : attribute.name,
+                    shader   : a.name.clone(),
+                }),
+                None => mismatches.push(LayoutMismatch::NotProvided(a.name.clone(), a.location)),
+            }
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> VertexLayout {
+        VertexLayout::new()
+            .buffer(&[VertexAttribute::new("position", 0, 3, gl::FLOAT, false),
+                      VertexAttribute::new("in_color", 1, 4, gl::UNSIGNED_BYTE, true)])
+            .buffer(&[VertexAttribute::new("in_normals", 3, 3, gl::FLOAT, false)])
+    }
+
+    fn active(name: &str, location: i32, gl_type: u32) -> ActiveAttribute {
+        ActiveAttribute { name: name.to_string(), location, gl_type }
+    }
+
+    #[test]
+    fn matching_attributes_agree() {
+        assert!(layout().compare(&[active("position", 0, gl::FLOAT_VEC3),
+                                   active("in_color", 1, gl::FLOAT_VEC4),
+                                   active("in_normals", 3, gl::FLOAT_VEC3),
+                                   active("gl_VertexID", -1, gl::INT)]).is_empty());
+    }
+
+    #[test]
+    fn reports_attributes_at_other_locations() {
+        let mismatches = layout().compare(&[active("position", 0, gl::FLOAT_VEC3),
+                                            active("in_color", 2, gl::FLOAT_VEC4),
+                                            active("in_uv", 3, gl::FLOAT_VEC2)]);
+        assert!(matches!(mismatches[0], LayoutMismatch::WrongLocation { name: "in_color", layout: 1, shader: 2 }));
+        assert!(matches!(mismatches[1], LayoutMismatch::NotRead("in_normals")));
+        assert!(matches!(&mismatches[2], LayoutMismatch::LocationTaken { location: 3, layout: "in_normals", shader } if shader == "in_uv"));
+        assert_eq!(mismatches.len(), 3);
+        assert!(mismatches[0].is_error() && !mismatches[1].is_error() && mismatches[2].is_error());
+    }
+
+    #[test]
+    fn attributes_left_out_are_warnings() {
+        let mismatches = layout().compare(&[active("position", 0, gl::FLOAT_VEC3),
+                                            active("in_color", 1, gl::FLOAT_VEC4),
+                                            active("in_uv", 2, gl::FLOAT_VEC2)]);
+        assert!(matches!(mismatches[0], LayoutMismatch::NotRead("in_normals")));
+        assert!(matches!(&mismatches[1], LayoutMismatch::NotProvided(name, 2) if name == "in_uv"));
+        assert_eq!(mismatches.len(), 2);
+        assert!(!mismatches.iter().any(LayoutMismatch::is_error));
+    }
+
+    #[test]
+    fn extra_components_are_a_warning() {
+        let mismatches = layout().compare(&[active("position", 0, gl::FLOAT_VEC2),
+                                            active("in_color", 1, gl::FLOAT_VEC4),
+                                            active("in_normals", 3, gl::FLOAT_VEC3)]);
+        assert!(matches!(mismatches[..], [LayoutMismatch::ExtraComponents { name: "position", layout: 3, shader: 2 }]));
+        assert!(!mismatches[0].is_error());
+    }
+
+    #[test]
+    fn integer_and_double_inputs_are_errors() {
+        let mismatches = layout().compare(&[active("position", 0, gl::DOUBLE_VEC3),
+                                            active("in_color", 1, gl::UNSIGNED_INT_VEC4),
+                                            active("in_normals", 3, gl::INT_VEC3)]);
+        let base_types: Vec<_> = mismatches.iter().map(|mismatch| match mismatch {
+            LayoutMismatch::WrongBaseType { shader, .. } => *shader,
+            other => panic!("unexpected mismatch: {}", other),
+        }).collect();
+        assert_eq!(base_types, ["double", "uint", "int"]);
+        assert!(mismatches.iter().all(LayoutMismatch::is_error));
+    }
+}
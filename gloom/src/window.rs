@@ -0,0 +1,182 @@
+use crate::debug_output::{self, DebugConfig};
+use crate::util;
+use std::sync::{Mutex, Arc, RwLock};
+use std::thread;
+
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, MouseButton, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+use glutin::event_loop::ControlFlow;
+
+// The window and the threads driving it, the same for every assignment.
+//
+// `run` opens the window and calls the render function on a thread of its own, where the OpenGL
+// context is current, while the event loop handles window events on the main thread. What the render
+// thread needs to know of the events reaches it through the shared state of `Input`.
+
+pub type Context = glutin::ContextWrapper<glutin::PossiblyCurrent, glutin::window::Window>;
+
+// Input gathered by the event loop, for the render thread to read and reset
+#[derive(Clone)]
+pub struct Input {
+    pub pressed_keys    : Arc<Mutex<Vec<VirtualKeyCode>>>, // Currently held down
+    pub mouse_delta     : Arc<Mutex<(f32, f32)>>,          // Movement since it was last reset, in pixels
+    pub cursor_position : Arc<Mutex<(f32, f32)>>,          // In pixels from the top left corner of the window
+    pub mouse_clicks    : Arc<Mutex<Vec<MouseButton>>>,    // Buttons pressed since they were last cleared
+    pub window_size     : Arc<Mutex<(u32, u32, bool)>>,    // Width, height, and whether it changed since last handled
+}
+
+impl Input {
+    fn new(width: u32, height: u32) -> Input {
+        Input {
+            pressed_keys    : Arc::new(Mutex::new(Vec::with_capacity(10))),
+            mouse_delta     : Arc::new(Mutex::new((0.0, 0.0))),
+            cursor_position : Arc::new(Mutex::new((0.0, 0.0))),
+            mouse_clicks    : Arc::new(Mutex::new(Vec::with_capacity(4))),
+            window_size     : Arc::new(Mutex::new((width, height, false))),
+        }
+    }
+}
+
+// The OpenGL state all the assignments start from
+unsafe fn set_up_gl(debug: DebugConfig) {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthFunc(gl::LESS);
+    gl::Enable(gl::CULL_FACE);
+    gl::Disable(gl::MULTISAMPLE);
+    gl::Enable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    debug_output::install(debug);
+
+    // Print some diagnostics
+    println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
+    println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
+    println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
+}
+
+// Opens a window of the given size, and calls `render` on the render thread once OpenGL is set up.
+// Returns when the window is closed, Escape or Q is pressed, or the render thread panics.
+pub fn run<F>(title: &str, width: u32, height: u32, render: F) -> !
+where
+    F: FnOnce(Context, Input) + Send + 'static,
+{
+    run_with_debug(title, width, height, DebugConfig::default(), render)
+}
+
+// As `run`, with the OpenGL debug output set up by `debug` rather than by its defaults. When it is
+// enabled the context is created as a debug context, for the driver to report all it can. Errors are
+// only raised by `debug_output::check`, which `render` should call once a frame.
+pub fn run_with_debug<F>(title: &str, width: u32, height: u32, debug: DebugConfig, render: F) -> !
+where
+    F: FnOnce(Context, Input) + Send + 'static,
+{
+    // Set up the necessary objects to deal with windows and event handling
+    let el = glutin::event_loop::EventLoop::new();
+    let wb = glutin::window::WindowBuilder::new()
+        .with_title(title)
+        .with_resizable(true)
+        .with_inner_size(glutin::dpi::LogicalSize::new(width, height));
+    let cb = glutin::ContextBuilder::new()
+        .with_vsync(true)
+        .with_gl_debug_flag(debug.enabled);
+    let windowed_context = cb.build_windowed(wb, &el).unwrap();
+    // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
+    // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
+    // windowed_context.window().set_cursor_visible(false);
+
+    // Shared with the render thread, which gets its own references
+    let input = Input::new(width, height);
+    let render_input = input.clone();
+
+    // Spawn a separate thread for rendering, so event handling doesn't block rendering
+    let render_thread = thread::spawn(move || {
+        // Acquire the OpenGL Context and load the function pointers.
+        // This has to be done inside of the rendering thread, because
+        // an active OpenGL context cannot safely traverse a thread boundary
+        let context = unsafe {
+            let c = windowed_context.make_current().unwrap();
+            gl::load_with(|symbol| c.get_proc_address(symbol) as *const _);
+            c
+        };
+        unsafe { set_up_gl(debug) };
+        render(context, render_input);
+    });
+
+    // Keep track of the health of the rendering thread
+    let render_thread_healthy = Arc::new(RwLock::new(true));
+    let render_thread_watchdog = Arc::clone(&render_thread_healthy);
+    thread::spawn(move || {
+        if render_thread.join().is_err() {
+            if let Ok(mut health) = render_thread_watchdog.write() {
+                println!("Render thread panicked!");
+                *health = false;
+            }
+        }
+    });
+
+    // Start the event loop -- This is where window events are initially handled
+    el.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+
+        // Terminate program if render thread panics
+        if let Ok(health) = render_thread_healthy.read() {
+            if !*health {
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+
+        match event {
+            Event::WindowEvent { event: WindowEvent::Resized(physical_size), .. } => {
+                println!("New window size received: {}x{}", physical_size.width, physical_size.height);
+                if let Ok(mut new_size) = input.window_size.lock() {
+                    *new_size = (physical_size.width, physical_size.height, true);
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                *control_flow = ControlFlow::Exit;
+            }
+            // Keep track of currently pressed keys to send to the rendering thread
+            Event::WindowEvent { event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput { state: key_state, virtual_keycode: Some(keycode), .. }, .. }, .. } => {
+
+                if let Ok(mut keys) = input.pressed_keys.lock() {
+                    match key_state {
+                        Released => {
+                            if let Some(i) = keys.iter().position(|&k| k == keycode) {
+                                keys.remove(i);
+                            }
+                        },
+                        Pressed => {
+                            if !keys.contains(&keycode) {
+                                keys.push(keycode);
+                            }
+                        }
+                    }
+                }
+
+                // Handle Escape and Q keys separately
+                match keycode {
+                    Escape => { *control_flow = ControlFlow::Exit; }
+                    Q      => { *control_flow = ControlFlow::Exit; }
+                    _      => { }
+                }
+            }
+            // Keep track of the cursor and of mouse clicks, for picking in the rendering thread
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                if let Ok(mut cursor) = input.cursor_position.lock() {
+                    *cursor = (position.x as f32, position.y as f32);
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::MouseInput { state: Pressed, button, .. }, .. } => {
+                if let Ok(mut clicks) = input.mouse_clicks.lock() {
+                    clicks.push(button);
+                }
+            }
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                // Accumulate mouse movement
+                if let Ok(mut position) = input.mouse_delta.lock() {
+                    *position = (position.0 + delta.0 as f32, position.1 + delta.1 as f32);
+                }
+            }
+            _ => { }
+        }
+    });
+}
@@ -0,0 +1,479 @@
+// Checks shaders without a GPU, so broken ones are caught by `cargo test` rather than when the
+// render thread builds them.
+//
+// Every stage in a directory is run through the preprocessor of gloom, with the same includes and
+// defines as at runtime, and the vertex, fragment and compute stages then through the GLSL front end
+// and validator of naga. naga knows no tessellation or geometry stages, which are only preprocessed.
+// Where a vertex and a fragment stage share a name, such as simple.vert and simple.frag, the inputs of
+// the fragment stage are also matched with the outputs of the vertex stage by location.
+//
+// naga reads GLSL as written for Vulkan, which differs from ours in a few ways: it starts at version
+// 440, so older #version lines are read as 450, it needs explicit locations on everything passed
+// between stages, and it has no combined samplers such as sampler2D, which are split into a texture and
+// a sampler the way Vulkan has them. It does not implement all of GLSL either, and stages using what it
+// lacks, such as arrays of samplers, are reported as skipped rather than broken. Vertex stages
+// followed by tessellation, told by a .tcs or .tes of the same name, need not write gl_Position.
+
+use naga::front::glsl;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+// Something wrong with a shader, at a line of one of its files when that is known
+#[derive(Debug)]
+pub struct Problem {
+    pub file    : String,
+    pub line    : Option<usize>,
+    pub message : String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None       => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+// What came of checking the stages of a directory
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checked  : usize,        // Stages read
+    pub problems : Vec<Problem>, // What is wrong with them
+    pub skipped  : Vec<Problem>, // Stages naga could not check in full, and why
+}
+
+// What came of checking one stage
+pub enum Checked {
+    Validated(Box<naga::Module>),
+    Preprocessed,         // A stage naga does not read
+    Unsupported(Problem), // Using what naga does not implement, so only preprocessed
+}
+
+// The stage naga reads a file as, by its extension. None for stages it cannot read and for other files.
+fn naga_stage(path: &Path) -> Option<naga::ShaderStage> {
+    match path.extension()?.to_str()? {
+        "vert" => Some(naga::ShaderStage::Vertex),
+        "frag" => Some(naga::ShaderStage::Fragment),
+        "comp" => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+fn is_stage(path: &Path) -> bool {
+    matches!(path.extension().and_then(|ext| ext.to_str()), Some("vert" | "frag" | "comp" | "tcs" | "tes" | "geom"))
+}
+
+// Checks every stage in `directory`, not those in its subdirectories, which hold included files
+pub fn check_directory(directory: &Path, defines: &[(String, String)]) -> io::Result<Report> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_file() && is_stage(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut report = Report { checked: paths.len(), ..Report::default() };
+    let mut modules = vec![];
+    for path in &paths {
+        match check_file(path, defines) {
+            Ok(Checked::Validated(module)) => modules.push((path, module)),
+            Ok(Checked::Preprocessed) => { },
+            Ok(Checked::Unsupported(reason)) => report.skipped.push(reason),
+            Err(mut problems) => report.problems.append(&mut problems),
+        }
+    }
+
+    for (vertex_path, vertex) in modules.iter().filter(|(path, _)| naga_stage(path) == Some(naga::ShaderStage::Vertex)) {
+        let fragment_path = vertex_path.with_extension("frag");
+        if let Some((_, fragment)) = modules.iter().find(|(path, _)| **path == fragment_path) {
+            report.problems.append(&mut interface_mismatches(vertex, &vertex_path.to_string_lossy(), fragment, &fragment_path.to_string_lossy()));
+        }
+    }
+    Ok(report)
+}
+
+// Preprocesses a stage and, when naga can read it, parses and validates it
+pub fn check_file(path: &Path, defines: &[(String, String)]) -> Result<Checked, Vec<Problem>> {
+    let file = path.to_string_lossy().to_string();
+    let (source, files) = gloom::shader::preprocess(&file, defines)
+        .map_err(|e| vec![Problem { file: file.clone(), line: None, message: e.to_string() }])?;
+    let tessellated = path.with_extension("tcs").is_file() || path.with_extension("tes").is_file();
+    match naga_stage(path) {
+        Some(stage) => parse(stage, &source, &files, tessellated),
+        None => Ok(Checked::Preprocessed),
+    }
+}
+
+// Parses and validates preprocessed source, `files` naming each of its source strings. `tessellated`
+// tells a vertex stage it is followed by tessellation stages.
+pub fn parse(stage: naga::ShaderStage, source: &str, files: &[String], tessellated: bool) -> Result<Checked, Vec<Problem>> {
+    let source = split_combined_samplers(&as_vulkan_version(source));
+    let problem = |span: naga::Span, message: String| {
+        let (file, line) = span.to_range().map_or((files[0].clone(), None), |range| locate(&source, files, range.start));
+        Problem { file, line, message }
+    };
+    let module = match glsl::Frontend::default().parse(&glsl::Options::from(stage), &source) {
+        Ok(module) => module,
+        Err(errors) => {
+            if let Some(error) = errors.errors.iter().find(|error| matches!(error.kind, glsl::ErrorKind::NotImplemented(_))) {
+                return Ok(Checked::Unsupported(problem(error.meta, error.kind.to_string())));
+            }
+            return Err(errors.errors.iter().map(|error| problem(error.meta, error.kind.to_string())).collect());
+        }
+    };
+
+    let mut validator = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all());
+    if let Err(error) = validator.validate(&module) {
+        if tessellated && matches!(error.as_inner(), naga::valid::ValidationError::EntryPoint {
+            source: naga::valid::EntryPointError::MissingVertexOutputPosition, ..
+        }) {
+            return Ok(Checked::Validated(Box::new(module)));
+        }
+        let span = error.spans().next().map_or(naga::Span::default(), |(span, _)| *span);
+        let mut message = error.as_inner().to_string();
+        let mut cause = std::error::Error::source(error.as_inner());
+        while let Some(error) = cause {
+            message.push_str(&format!(": {}", error));
+            cause = error.source();
+        }
+        return Err(vec![problem(span, message)]);
+    }
+    Ok(Checked::Validated(Box::new(module)))
+}
+
+// The source with a #version older than 440 read as 450, the same length so offsets still hold
+fn as_vulkan_version(source: &str) -> String {
+    source.lines().map(|line| {
+        let version = line.trim_start().strip_prefix("#version").map(|rest| rest.trim_start());
+        match version.and_then(|rest| rest.get(..3)).and_then(|number| number.parse::<u32>().ok()) {
+            Some(number) if number < 440 => line.replacen(&number.to_string(), "450", 1),
+            _ => line.to_string(),
+        }
+    }).collect::<Vec<_>>().join("\n")
+}
+
+// The identifiers, keywords and numbers of a line, with the byte offset of each
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+        match (start, c.is_ascii_alphanumeric() || c == '_') {
+            (None, true) => start = Some(i),
+            (Some(from), false) => {
+                words.push((from, &line[from..i]));
+                start = None;
+            }
+            _ => { },
+        }
+    }
+    words
+}
+
+// For a combined sampler type such as sampler2D or usampler2DShadow, the texture and the sampler type
+// it is made of in GLSL for Vulkan
+fn split_sampler_type(ty: &str) -> Option<(String, &'static str)> {
+    let unprefixed = ty.strip_prefix(['i', 'u']).unwrap_or(ty);
+    let dimensions = unprefixed.strip_prefix("sampler")?;
+    let (dimensions, sampler) = match dimensions.strip_suffix("Shadow") {
+        Some(dimensions) => (dimensions, "samplerShadow"),
+        None => (dimensions, "sampler"),
+    };
+    match dimensions {
+        "1D" | "2D" | "3D" | "Cube" | "1DArray" | "2DArray" | "CubeArray" | "2DMS" | "2DMSArray" =>
+            Some((format!("{}texture{}", &ty[..ty.len() - unprefixed.len()], dimensions), sampler)),
+        _ => None,
+    }
+}
+
+// The source with each `uniform sampler2D name;` declared as a texture `name` and a sampler
+// `name_sampler`, and each use of `name` as `sampler2D(name, name_sampler)`. OpenGL numbers texture
+// units apart from the bindings of blocks, so the textures go in set 1 and the samplers in set 2 for
+// their bindings not to collide with those of the blocks or each other. Lines are only rewritten in
+// place, so line numbers still hold. Arrays of samplers are left as they are.
+fn split_combined_samplers(source: &str) -> String {
+    let mut samplers: Vec<(&str, &str)> = vec![]; // The name and type of each sampler split so far
+    let mut lines = vec![];
+    for line in source.lines() {
+        let words = words(line);
+        let declaration = words.windows(3).find(|w| w[0].1 == "uniform" && split_sampler_type(w[1].1).is_some());
+        match declaration {
+            Some(w) if line[w[2].0 + w[2].1.len()..].trim_start().starts_with(';') => {
+                let (ty, name) = (w[1].1, w[2].1);
+                let (texture, sampler) = split_sampler_type(ty).unwrap();
+                let binding = words.windows(2).find(|w| w[0].1 == "binding").map_or("0", |w| w[1].1);
+                let rest = &line[w[2].0 + name.len()..];
+                lines.push(format!("layout(set = 1, binding = {}) uniform {} {}; layout(set = 2, binding = {}) uniform {} {}_sampler{}",
+                                   binding, texture, name, binding, sampler, name, rest));
+                samplers.push((name, ty));
+            }
+            _ => {
+                let mut rewritten = String::new();
+                let mut end = 0;
+                for (start, word) in words {
+                    if let Some((name, ty)) = samplers.iter().find(|(name, _)| *name == word) {
+                        rewritten.push_str(&line[end..start]);
+                        rewritten.push_str(&format!("{}({}, {}_sampler)", ty, name, name));
+                        end = start + word.len();
+                    }
+                }
+                rewritten.push_str(&line[end..]);
+                lines.push(rewritten);
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+// The file and the line in it of a byte offset in preprocessed source, told by the `#line line source`
+// directives the preprocessor writes
+pub fn locate(source: &str, files: &[String], offset: usize) -> (String, Option<usize>) {
+    let mut file = 0;
+    let mut line = 1;
+    let before = &source[..offset.min(source.len())];
+    let lines_before = before.rfind('\n').map(|end| before[..end].split('\n'));
+    for text in lines_before.into_iter().flatten() {
+        let directive = text.trim_start().strip_prefix("#line").map(|rest| rest.split_whitespace().collect::<Vec<_>>());
+        match directive.as_deref() {
+            Some([next_line, number, ..]) => {
+                line = next_line.parse().unwrap_or(line);
+                file = number.parse().unwrap_or(file);
+            }
+            Some([next_line]) => line = next_line.parse().unwrap_or(line),
+            _ => line += 1,
+        }
+    }
+    (files.get(file).cloned().unwrap_or_else(|| files[0].clone()), Some(line))
+}
+
+// A value passed between stages: its name, and its type as GLSL names it
+struct Varying {
+    name : String,
+    ty   : String,
+}
+
+// The values an entry point takes in or gives out at each location
+fn varyings(module: &naga::Module, outputs: bool) -> Vec<(u32, Varying)> {
+    let mut varyings = vec![];
+    let mut add = |name: &Option<String>, ty: naga::Handle<naga::Type>, binding: &Option<naga::Binding>| {
+        if let Some(naga::Binding::Location { location, .. }) = binding {
+            let name = name.clone().unwrap_or_default();
+            varyings.push((*location, Varying { name, ty: type_name(module, ty) }));
+        }
+    };
+    for entry_point in &module.entry_points {
+        let function = &entry_point.function;
+        let bound: Vec<(Option<String>, naga::Handle<naga::Type>, Option<naga::Binding>)> = if outputs {
+            function.result.iter().map(|result| (None, result.ty, result.binding.clone())).collect()
+        } else {
+            function.arguments.iter().map(|argument| (argument.name.clone(), argument.ty, argument.binding.clone())).collect()
+        };
+        for (name, ty, binding) in bound {
+            match &module.types[ty].inner {
+                naga::TypeInner::Struct { members, .. } if binding.is_none() => {
+                    for member in members {
+                        add(&member.name, member.ty, &member.binding);
+                    }
+                }
+                _ => add(&name, ty, &binding),
+            }
+        }
+    }
+    varyings
+}
+
+fn type_name(module: &naga::Module, ty: naga::Handle<naga::Type>) -> String {
+    use naga::{ScalarKind::*, TypeInner};
+    let prefix = |kind| match kind {
+        Float => "",
+        Sint => "i",
+        Uint => "u",
+        Bool => "b",
+        _ => "?",
+    };
+    match &module.types[ty].inner {
+        TypeInner::Scalar(scalar) => match scalar.kind {
+            Float => "float".to_string(),
+            Sint => "int".to_string(),
+            Uint => "uint".to_string(),
+            Bool => "bool".to_string(),
+            _ => format!("{:?}", scalar),
+        },
+        TypeInner::Vector { size, scalar } => format!("{}vec{}", prefix(scalar.kind), *size as u8),
+        TypeInner::Matrix { columns, rows, .. } => format!("mat{}x{}", *columns as u8, *rows as u8),
+        other => format!("{:?}", other),
+    }
+}
+
+// Where the fragment stage reads a location the vertex stage does not write, or reads it as another type
+pub fn interface_mismatches(vertex: &naga::Module, vertex_file: &str, fragment: &naga::Module, fragment_file: &str) -> Vec<Problem> {
+    let outputs = varyings(vertex, true);
+    let mut problems = vec![];
+    for (location, input) in varyings(fragment, false) {
+        let message = match outputs.iter().find(|(output_location, _)| *output_location == location) {
+            None => format!("{} reads location {}, which {} does not write", input.name, location, vertex_file),
+            Some((_, output)) if output.ty != input.ty =>
+                format!("{} reads location {} as {}, but {} writes {} there as {}",
+                        input.name, location, input.ty, vertex_file, output.name, output.ty),
+            Some(_) => continue,
+        };
+        problems.push(Problem { file: fragment_file.to_string(), line: None, message });
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<String> {
+        vec!["test.glsl".to_string(), "included.glsl".to_string()]
+    }
+
+    fn module(stage: naga::ShaderStage, source: &str) -> naga::Module {
+        match parse(stage, source, &files(), false) {
+            Ok(Checked::Validated(module)) => *module,
+            Ok(_) => panic!("not validated"),
+            Err(problems) => panic!("{:?}", problems),
+        }
+    }
+
+    const VERTEX: &str = "#version 430 core
+layout(location = 0) in vec3 position;
+layout(location = 1) out vec4 out_color;
+layout(location = 3) out vec3 out_normals;
+void main() {
+    out_color = vec4(1.0);
+    out_normals = position;
+    gl_Position = vec4(position, 1.0);
+}
+";
+
+    #[test]
+    fn matching_interfaces_pass() {
+        let fragment = module(naga::ShaderStage::Fragment, "#version 430 core
+layout(location = 1) in vec4 in_color;
+layout(location = 3) in vec3 in_normals;
+layout(location = 0) out vec4 color;
+void main() {
+    color = in_color * in_normals.x;
+}
+");
+        let vertex = module(naga::ShaderStage::Vertex, VERTEX);
+        assert!(interface_mismatches(&vertex, "a.vert", &fragment, "a.frag").is_empty());
+    }
+
+    #[test]
+    fn unwritten_location_is_reported() {
+        let fragment = module(naga::ShaderStage::Fragment, "#version 430 core
+layout(location = 2) in vec4 in_color;
+layout(location = 0) out vec4 color;
+void main() {
+    color = in_color;
+}
+");
+        let vertex = module(naga::ShaderStage::Vertex, VERTEX);
+        let problems = interface_mismatches(&vertex, "a.vert", &fragment, "a.frag");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("location 2"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn mismatched_type_is_reported() {
+        let fragment = module(naga::ShaderStage::Fragment, "#version 430 core
+layout(location = 3) in vec4 in_normals;
+layout(location = 0) out vec4 color;
+void main() {
+    color = in_normals;
+}
+");
+        let vertex = module(naga::ShaderStage::Vertex, VERTEX);
+        let problems = interface_mismatches(&vertex, "a.vert", &fragment, "a.frag");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("as vec4") && problems[0].message.contains("as vec3"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn errors_are_located_in_included_files() {
+        let source = "#version 430 core
+#line 2 0
+layout(location = 0) out vec4 color;
+#line 1 1
+float broken() { return undefined_name; }
+#line 4 0
+void main() { color = vec4(broken()); }
+";
+        let problems = parse(naga::ShaderStage::Fragment, source, &files(), false).err().unwrap();
+        assert_eq!(problems[0].file, "included.glsl");
+        assert_eq!(problems[0].line, Some(1));
+    }
+
+    #[test]
+    fn position_is_only_needed_without_tessellation() {
+        let source = "#version 430 core
+layout(location = 0) in vec3 position;
+layout(location = 0) out vec3 tcs_position;
+void main() { tcs_position = position; }
+";
+        assert!(parse(naga::ShaderStage::Vertex, source, &files(), false).is_err());
+        assert!(matches!(parse(naga::ShaderStage::Vertex, source, &files(), true), Ok(Checked::Validated(_))));
+    }
+
+    #[test]
+    fn unimplemented_glsl_is_skipped() {
+        let source = "#version 430 core
+layout(binding = 1) uniform sampler2D albedos[2];
+layout(location = 0) out vec4 color;
+void main() { color = texture(albedos[1], vec2(0.5)); }
+";
+        assert!(matches!(parse(naga::ShaderStage::Fragment, source, &files(), false), Ok(Checked::Unsupported(_))));
+    }
+
+    #[test]
+    fn combined_samplers_are_split() {
+        let source = "#version 430 core
+layout(binding = 1) uniform sampler2D albedo; // base color
+layout(binding = 2) uniform sampler2DShadow shadow_map;
+layout(location = 0) out vec4 color;
+void main() {
+    vec2 size = vec2(textureSize(albedo, 0));
+    color = texture(albedo, size) * texture(shadow_map, vec3(0.5));
+}
+";
+        assert_eq!(split_combined_samplers(source).lines().collect::<Vec<_>>(), [
+            "#version 430 core",
+            "layout(set = 1, binding = 1) uniform texture2D albedo; layout(set = 2, binding = 1) uniform sampler albedo_sampler; // base color",
+            "layout(set = 1, binding = 2) uniform texture2D shadow_map; layout(set = 2, binding = 2) uniform samplerShadow shadow_map_sampler;",
+            "layout(location = 0) out vec4 color;",
+            "void main() {",
+            "    vec2 size = vec2(textureSize(sampler2D(albedo, albedo_sampler), 0));",
+            "    color = texture(sampler2D(albedo, albedo_sampler), size) * texture(sampler2DShadow(shadow_map, shadow_map_sampler), vec3(0.5));",
+            "}",
+        ]);
+        assert!(matches!(parse(naga::ShaderStage::Fragment, source, &files(), false), Ok(Checked::Validated(_))));
+    }
+
+    #[test]
+    fn sampler_types_split() {
+        assert_eq!(split_sampler_type("sampler2D"), Some(("texture2D".to_string(), "sampler")));
+        assert_eq!(split_sampler_type("usamplerCubeArray"), Some(("utextureCubeArray".to_string(), "sampler")));
+        assert_eq!(split_sampler_type("sampler2DArrayShadow"), Some(("texture2DArray".to_string(), "samplerShadow")));
+        assert_eq!(split_sampler_type("sampler"), None);
+        assert_eq!(split_sampler_type("samplerBuffer"), None);
+    }
+
+    #[test]
+    fn lines_follow_line_directives() {
+        let source = "#version 430 core\n#define MAX_LIGHTS 4\n#line 2 0\nfirst\nsecond\n";
+        let offset = source.find("second").unwrap();
+        assert_eq!(locate(source, &files(), offset), ("test.glsl".to_string(), Some(3)));
+    }
+
+    #[test]
+    fn old_versions_are_read_as_450() {
+        assert_eq!(as_vulkan_version("#version 330 core\nvoid main() {}"), "#version 450 core\nvoid main() {}");
+        assert_eq!(as_vulkan_version("#version 460"), "#version 460");
+    }
+}
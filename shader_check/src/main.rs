@@ -0,0 +1,49 @@
+use std::path::Path;
+use std::process;
+
+// Checks the shaders of a directory without a GPU, see lib.rs. Run from an assignment:
+//   cargo run -p shader_check -- -D MAX_LIGHTS=4 shaders
+// Each -D defines a name for every stage, as the program would at runtime. Exits with 1 when any
+// shader is broken, after printing what is wrong with each.
+
+fn usage() -> ! {
+    eprintln!("usage: shader_check [-D NAME=VALUE]... [DIRECTORY]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut defines = vec![];
+    let mut directory = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(define) = arg.strip_prefix("-D") {
+            let define = if define.is_empty() { args.next().unwrap_or_else(|| usage()) } else { define.to_string() };
+            let (name, value) = define.split_once('=').unwrap_or((&define, "1"));
+            defines.push((name.to_string(), value.to_string()));
+        } else if arg.starts_with('-') || directory.is_some() {
+            usage();
+        } else {
+            directory = Some(arg);
+        }
+    }
+    let directory = directory.unwrap_or_else(|| "shaders".to_string());
+
+    match shader_check::check_directory(Path::new(&directory), &defines) {
+        Ok(report) => {
+            for skipped in &report.skipped {
+                println!("{} (not checked by naga)", skipped);
+            }
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            println!("{}: checked {} shaders, {} problems", directory, report.checked, report.problems.len());
+            if !report.problems.is_empty() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("could not read {}: {}", directory, e);
+            process::exit(2);
+        }
+    }
+}